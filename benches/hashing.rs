@@ -0,0 +1,47 @@
+// Throughput of the hashing used by the validation. Run with `cargo bench`.
+
+use std::fs::File;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use md5::Md5;
+
+#[path = "../src/checksums/hash.rs"]
+#[allow(dead_code)]
+mod hash;
+
+const MIB: usize = 1024 * 1024;
+const RUNS: u32 = 5;
+
+fn measure<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    f();
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    println!("{:<40} {:>8.1} MiB/s", name, bytes as f64 / MIB as f64 / best.as_secs_f64());
+}
+
+fn main() {
+    let data = (0..256 * MIB).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    let mut file = tempfile::NamedTempFile::new().expect("Could not create temporary file");
+    file.write_all(&data).expect("Could not write temporary file");
+    file.flush().expect("Could not write temporary file");
+
+    measure("md5sum (memory)", data.len(), || {
+        hash::md5sum::<Md5, _>(&mut &data[..]).unwrap();
+    });
+    measure("md5sum (file, 1 MiB buffer)", data.len(), || {
+        hash::md5sum::<Md5, _>(&mut File::open(file.path()).unwrap()).unwrap();
+    });
+    measure("md5sum_file (memory mapped)", data.len(), || {
+        hash::md5sum_file::<Md5>(file.path()).unwrap();
+    });
+
+    let small = &data[..16 * MIB];
+    file.as_file().set_len(small.len() as u64).expect("Could not truncate temporary file");
+    measure("md5sum_file (16 MiB, buffered)", small.len(), || {
+        hash::md5sum_file::<Md5>(file.path()).unwrap();
+    });
+}
@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Take};
+use std::path::PathBuf;
+use md5::Md5;
+use super::md5sum;
+
+const MAGIC_BIGF: &[u8; 4] = b"BIGF";
+const MAGIC_BIG4: &[u8; 4] = b"BIG4";
+/// Magic, archive size, entry count and header size
+const HEADER_PREFIX_SIZE: u64 = 16;
+/// Offset, size and at least the null terminator of the path
+const MIN_INDEX_RECORD_SIZE: u64 = 9;
+
+/// A single file stored inside a .big archive
+#[derive(Debug, Clone)]
+pub struct BigEntry {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32
+}
+
+/// Reader for the BIG archive format used by SAGE engine games.
+///
+/// Layout: 4 byte magic ("BIGF" or "BIG4"), archive size (u32 LE), entry count (u32 BE),
+/// header size (u32 BE), followed by one index record per entry consisting of
+/// offset (u32 BE), size (u32 BE) and a null terminated path.
+pub struct BigArchive<R> {
+    reader: R,
+    entries: Vec<BigEntry>
+}
+
+impl<R: Read + Seek> BigArchive<R> {
+    pub fn open(mut reader: R) -> Result<BigArchive<R>, String> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+        if &magic != MAGIC_BIGF && &magic != MAGIC_BIG4 {
+            return Err(format!("Not a BIG archive (magic was {:?})", magic));
+        }
+        let _archive_size = read_u32_le(&mut reader)?;
+        let entry_count = read_u32_be(&mut reader)?;
+        let header_size = read_u32_be(&mut reader)?;
+
+        // The header values are untrusted, so they are checked against the actual length before allocating anything
+        let length = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        reader.seek(SeekFrom::Start(HEADER_PREFIX_SIZE)).map_err(|e| e.to_string())?;
+        let index_size = (header_size as u64).min(length).saturating_sub(HEADER_PREFIX_SIZE);
+        if entry_count as u64 * MIN_INDEX_RECORD_SIZE > index_size {
+            return Err(format!("Invalid entry count {} for a header of {} bytes", entry_count, header_size));
+        }
+
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let offset = read_u32_be(&mut reader)?;
+            let size = read_u32_be(&mut reader)?;
+            let name = read_cstring(&mut reader)?;
+            if offset as u64 + size as u64 > length {
+                return Err(format!("Entry {} exceeds the archive ({} bytes at {})", name, size, offset));
+            }
+            entries.push(BigEntry { name, offset, size });
+        }
+
+        Ok(BigArchive { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[BigEntry] {
+        &self.entries
+    }
+
+    pub fn entry_reader(&mut self, entry: &BigEntry) -> Result<Take<&mut R>, String> {
+        self.reader.seek(SeekFrom::Start(entry.offset as u64)).map_err(|e| e.to_string())?;
+        Ok((&mut self.reader).take(entry.size as u64))
+    }
+}
+
+pub fn is_big_archive(path: &str) -> bool {
+    path.to_lowercase().ends_with(".big")
+}
+
+/// Calculates the md5 checksum of every entry inside the given .big file.
+/// Results are sorted by entry name.
+pub fn calculate_entry_hashes(path: PathBuf) -> Result<Vec<(String, String)>, String> {
+    let file = File::open(&path)
+        .map_err(|e| format!("Error reading file {}: {}", path.to_str().unwrap(), e))?;
+    let mut archive = BigArchive::open(BufReader::new(file))
+        .map_err(|e| format!("Error reading BIG archive {}: {}", path.to_str().unwrap(), e))?;
+
+    let mut results = archive.entries().to_vec().iter()
+        .map(|entry| {
+            let mut reader = archive.entry_reader(entry)?;
+            let md5 = md5sum::<Md5, _>(&mut reader)?;
+            Ok((entry.name.clone(), format!("{:x}", md5)))
+        })
+        .collect::<Result<Vec<(String, String)>, String>>()?;
+    results.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+    Ok(results)
+}
+
+fn read_u32_le<R: Read>(reader: &mut R) -> Result<u32, String> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).map_err(|e| e.to_string())?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u32_be<R: Read>(reader: &mut R) -> Result<u32, String> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).map_err(|e| e.to_string())?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_cstring<R: Read>(reader: &mut R) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte).map_err(|e| e.to_string())?;
+        if byte[0] == 0 {
+            break;
+        }
+        bytes.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn archive(entry_count: u32, header_size: u32, entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let index_size: usize = entries.iter().map(|(name, _)| 9 + name.len()).sum();
+        let mut offset = 16 + index_size;
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC_BIGF);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&entry_count.to_be_bytes());
+        data.extend_from_slice(&header_size.to_be_bytes());
+        for (name, contents) in entries {
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+            offset += contents.len();
+        }
+        for (_, contents) in entries {
+            data.extend_from_slice(contents);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_entries() {
+        let data = archive(2, 16 + 9 + 5 + 9 + 5, &[("a.ini", b"hello"), ("b.ini", b"world!")]);
+        let mut archive = BigArchive::open(Cursor::new(data)).unwrap();
+        let entries = archive.entries().to_vec();
+        assert_eq!(entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>(), vec!["a.ini", "b.ini"]);
+        let mut contents = String::new();
+        archive.entry_reader(&entries[1]).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "world!");
+    }
+
+    #[test]
+    fn rejects_entry_count_beyond_header() {
+        let data = archive(u32::MAX, 16 + 9 + 5, &[("a.ini", b"hello")]);
+        assert!(BigArchive::open(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn rejects_header_size_beyond_file() {
+        let data = archive(1_000_000, u32::MAX, &[("a.ini", b"hello")]);
+        assert!(BigArchive::open(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn rejects_entry_beyond_file() {
+        let mut data = archive(1, 16 + 9 + 5, &[("a.ini", b"hello")]);
+        data.truncate(data.len() - 1);
+        assert!(BigArchive::open(Cursor::new(data)).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// Doesn't use any other module of the crate, so benches/hashing.rs can include it
+
+use std::fs::File;
+use std::io::{Read, ErrorKind};
+use std::path::Path;
+use md5::Digest;
+use md5::digest::Output;
+use memmap2::Mmap;
+
+/// Size of the (heap allocated) read buffer used for hashing
+const BUFFER_SIZE: usize = 1024 * 1024;
+/// Files of at least this size are memory mapped instead of being read through a buffer
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Hashes everything the reader returns until EOF. Short reads are not treated as EOF,
+/// since readers (e.g. `Take`, `BufReader` or pipes) may return less than requested at any time.
+pub fn md5sum<D: Digest + Default, R: Read>(reader: &mut R) -> Result<Output<D>, String> {
+    let mut sh = D::default();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => sh.update(&buffer[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string())
+        };
+    }
+
+    Ok(sh.finalize())
+}
+
+/// Hashes a file on disk. Large files (like the game's .big archives) are memory mapped.
+pub fn md5sum_file<D: Digest + Default>(path: &Path) -> Result<Output<D>, String> {
+    let mut file = File::open(path)
+        .map_err(|e| format!("Error reading file {}: {}", path.to_str().unwrap(), e))?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+
+    if size >= MMAP_THRESHOLD {
+        // Safety: The game files are not expected to be modified while being validated
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+        Ok(D::digest(&mmap))
+    } else {
+        md5sum::<D, _>(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Write};
+    use md5::Md5;
+    use super::*;
+
+    /// Test suite of RFC 1321, appendix A.5
+    const RFC_1321: [(&str, &str); 7] = [
+        ("", "d41d8cd98f00b204e9800998ecf8427e"),
+        ("a", "0cc175b9c0f1b6a831c399e269772661"),
+        ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+        ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+        ("abcdefghijklmnopqrstuvwxyz", "c3fcd3d76192e4007dfb496cca67e13b"),
+        ("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789", "d174ab98d277d9f5a5611c2c9f419d9f"),
+        ("12345678901234567890123456789012345678901234567890123456789012345678901234567890", "57edf4a22be3c955ac49da2e2107b67a")
+    ];
+
+    /// Returns at most `max_read` bytes per call and an `Interrupted` error before every other read
+    struct ShortReader<R> {
+        inner: R,
+        max_read: usize,
+        interrupt: bool
+    }
+
+    impl<R: Read> Read for ShortReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::Error::from(ErrorKind::Interrupted))
+            }
+            let n = buf.len().min(self.max_read);
+            self.inner.read(&mut buf[..n])
+        }
+    }
+
+    fn short_reader<R: Read>(inner: R, max_read: usize) -> ShortReader<R> {
+        ShortReader { inner, max_read, interrupt: false }
+    }
+
+    fn hex(data: &[u8]) -> String {
+        format!("{:x}", Md5::digest(data))
+    }
+
+    #[test]
+    fn rfc_1321_digests() {
+        for (input, digest) in RFC_1321.iter() {
+            let md5 = md5sum::<Md5, _>(&mut Cursor::new(input.as_bytes())).unwrap();
+            assert_eq!(format!("{:x}", md5), *digest, "md5(\"{}\")", input);
+        }
+    }
+
+    #[test]
+    fn rfc_1321_digests_with_short_reads() {
+        for max_read in [1, 3, 7, 64] {
+            for (input, digest) in RFC_1321.iter() {
+                let md5 = md5sum::<Md5, _>(&mut short_reader(input.as_bytes(), max_read)).unwrap();
+                assert_eq!(format!("{:x}", md5), *digest, "md5(\"{}\") in reads of {} bytes", input, max_read);
+            }
+        }
+    }
+
+    #[test]
+    fn short_reads_across_buffer_size() {
+        let data = (0..BUFFER_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let md5 = md5sum::<Md5, _>(&mut short_reader(Cursor::new(&data), BUFFER_SIZE - 1)).unwrap();
+        assert_eq!(format!("{:x}", md5), hex(&data));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(ErrorKind::Other, "broken pipe"))
+            }
+        }
+        assert!(md5sum::<Md5, _>(&mut FailingReader).is_err());
+    }
+
+    /// Files just below the threshold are read through the buffer, files from the threshold on are memory mapped
+    #[test]
+    fn files_across_mmap_threshold() {
+        let data = (0..MMAP_THRESHOLD + 1).map(|i| (i % 253) as u8).collect::<Vec<u8>>();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+
+        for size in [MMAP_THRESHOLD + 1, MMAP_THRESHOLD, MMAP_THRESHOLD - 1] {
+            file.as_file().set_len(size).unwrap();
+            let expected = hex(&data[..size as usize]);
+            let md5 = md5sum_file::<Md5>(file.path()).unwrap();
+            assert_eq!(format!("{:x}", md5), expected, "file of {} bytes", size);
+            let md5 = md5sum::<Md5, _>(&mut short_reader(File::open(file.path()).unwrap(), 4093)).unwrap();
+            assert_eq!(format!("{:x}", md5), expected, "short reads of {} bytes", size);
+        }
+    }
+}
@@ -3,37 +3,35 @@ use std::ffi::OsString;
 use std::iter::Copied;
 use iced::Subscription;
 use iced_native::subscription;
-use md5::{Digest, Md5};
-use md5::digest::Output;
-use std::io::{Read, Write};
-use std::path::{Path, PathBuf, StripPrefixError};
+use md5::Md5;
+use std::io::{Write, Cursor};
+use std::path::{PathBuf, StripPrefixError};
 use std::fs::{File, OpenOptions, self};
 use std::fs::{read_to_string};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 use iced_futures::{BoxStream, futures};
+use regex::RegexSet;
+use once_cell::sync::Lazy;
 use super::common::{InstallationProgress};
 
+pub mod big;
+pub mod hash;
+pub mod report;
 
-const BUFFER_SIZE: usize = 1024;
+pub use hash::{md5sum, md5sum_file};
 
-pub fn md5sum<D: Digest + Default, R: Read>(reader: &mut R) -> Result<Output<D>, String> {
-    let mut sh = D::default();
-    let mut buffer = [0u8; BUFFER_SIZE];
+/// Files and BIG archive entries which influence the game simulation (and thereby out of sync errors).
+/// Anything else (localized audio, speech, movies, ...) is ignored for the sync fingerprint.
+const SYNC_RELEVANT_PATTERNS: [&str; 4] = [
+    r"^data[\\/](ini|scripts)[\\/]",
+    r"^maps[\\/]",
+    r"^game\.dat$",
+    r"^[^\\/]+\.(exe|dll)$"
+];
 
-    loop {
-        let n = match reader.read(&mut buffer) {
-            Ok(n) => n,
-            Err(e) => return Err(e.to_string().to_owned())
-        };
-        sh.update(&buffer[..n]);
-        if n == 0 || n < BUFFER_SIZE {
-            break;
-        }
-    }
-
-    return Ok(sh.finalize())
-}
+static SYNC_RELEVANT: Lazy<RegexSet> = Lazy::new(|| RegexSet::new(&SYNC_RELEVANT_PATTERNS)
+    .expect("Invalid sync relevant pattern"));
 
 pub fn generate_files_list(path: PathBuf) -> Vec<String> {
     let install_path = &path;
@@ -67,7 +65,7 @@ pub fn generate_files_list(path: PathBuf) -> Vec<String> {
             install_path.read_dir()
                 .expect(format!("Error: Could not read game directory!").to_string().deref())
                 .filter_map(|r| r.ok())
-                .filter(|f| f.file_name() != "checksums.txt")
+                .filter(|f| f.file_name() != "checksums.txt" && f.file_name() != "big_checksums.txt")
                 .map(|f| match f.path().is_dir() {
                     true => {
                         generate_files_list(f.path().to_path_buf()).into_iter()
@@ -101,6 +99,37 @@ pub fn generate_files_list(path: PathBuf) -> Vec<String> {
 
 }
 
+/// Reads the checksums written by a previous validation (if any)
+pub fn read_checksums_file(install_path: &str) -> Vec<(String, String)> {
+    match read_to_string(PathBuf::from(install_path).join("checksums.txt")) {
+        Ok(contents) => contents
+            .replace("\r", "")
+            .split("\n")
+            .filter_map(|s| s.split_once("|"))
+            .map(|(path, checksum)| (path.to_owned(), checksum.to_owned()))
+            .collect(),
+        Err(_) => vec![]
+    }
+}
+
+/// Reads the BIG archive entry checksums written by a previous validation (if any), grouped by archive
+pub fn read_big_checksums_file(install_path: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut archives: Vec<(String, Vec<(String, String)>)> = vec![];
+    if let Ok(contents) = read_to_string(PathBuf::from(install_path).join("big_checksums.txt")) {
+        for line in contents.replace("\r", "").split("\n") {
+            let (archive, entry, checksum) = match line.splitn(3, "|").collect::<Vec<&str>>()[..] {
+                [archive, entry, checksum] => (archive, entry, checksum),
+                _ => continue
+            };
+            match archives.last_mut() {
+                Some((path, entries)) if path == archive => entries.push((entry.to_owned(), checksum.to_owned())),
+                _ => archives.push((archive.to_owned(), vec![(entry.to_owned(), checksum.to_owned())]))
+            }
+        }
+    }
+    archives
+}
+
 pub fn write_checksums_file<S>(install_path: S, results: Vec<(String, String)>) -> Result<(), std::io::Error> 
     where S: Into<String> 
 {
@@ -120,24 +149,51 @@ pub fn write_checksums_file<S>(install_path: S, results: Vec<(String, String)>)
     Ok(())
 }
 
+pub fn write_big_checksums_file<S>(install_path: S, results: Vec<(String, Vec<(String, String)>)>) -> Result<(), std::io::Error>
+    where S: Into<String>
+{
+    let checksum_path = PathBuf::from(&install_path.into()).join("big_checksums.txt");
+
+    let checksums = results.iter()
+        .flat_map(|(path, entries)| entries.iter()
+            .map(move |(entry, checksum)| format!("{}|{}|{}", path, entry, checksum)))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    println!("Creating {:?}...", checksum_path);
+    File::create(checksum_path)?
+        .write(checksums.as_bytes())?;
+    Ok(())
+}
+
+pub fn is_sync_relevant(path: &str) -> bool {
+    SYNC_RELEVANT.is_match(&path.to_lowercase())
+}
+
+/// Calculates the sync fingerprint over all sync relevant files and BIG archive entries.
+/// BIG entries are identified by their name only, so it doesn't matter which archive
+/// (e.g. englishpatch vs. germanpatch) contains them.
+pub fn calculate_sync_fingerprint(results: &Vec<(String, String, Option<Vec<(String, String)>>)>) -> Result<String, String> {
+    let mut relevant = results.iter()
+        .flat_map(|(path, checksum, entries)| match entries {
+            Some(entries) => entries.clone(),
+            None => vec![(path.clone(), checksum.clone())]
+        })
+        .filter(|(name, _)| is_sync_relevant(name))
+        .map(|(name, checksum)| format!("{}|{}", name.to_lowercase().replace("/", "\\"), checksum))
+        .collect::<Vec<String>>();
+    relevant.sort();
+
+    let fingerprint = md5sum::<Md5, _>(&mut Cursor::new(relevant.join("\n").as_bytes()))?;
+    Ok(format!("{:x}", fingerprint))
+}
+
 pub fn calculate_hash(path: PathBuf) -> Result<String, String> {
     if path.is_dir() {
         panic!("Cannot calculate hash for directory!")
     }
 
-    let mut file = File::open(&path)
-        .expect(format!("Error reading file {}", path.to_str().unwrap()).to_string().deref());
-    match md5sum::<Md5, _>(&mut file) {
-        Ok(md5) => {
-            let md5_str = md5.iter()
-                .map(|b| format!("{:02x}", b))
-                .collect::<Vec<String>>()
-                .join("");
-            Ok(md5_str)
-        },
-        Err(e) => Err(e.to_string())
-    }
-
+    md5sum_file::<Md5>(&path).map(|md5| format!("{:x}", md5))
 }
 
 pub struct ChecksumGenerator<I> {
@@ -173,10 +229,21 @@ async fn process_file<I: Copy>(id: I, state: State) -> Option<((I, InstallationP
             if path.ends_with("checksums.txt") {
                 Some(((id.into(), InstallationProgress::Skipped), State::Finished))
             } else {
-                match calculate_hash(PathBuf::from(&install_path).join(&path)) {
-                    Ok(cs) => {
-                        Some(((id, InstallationProgress::ChecksumResult(path, cs)), State::Finished))
+                let file_path = PathBuf::from(&install_path).join(&path);
+                let result = match calculate_hash(file_path.clone()) {
+                    // A malformed archive is still covered by the checksum of the whole file
+                    Ok(cs) if big::is_big_archive(&path) => match big::calculate_entry_hashes(file_path) {
+                        Ok(entries) => Ok(InstallationProgress::BigChecksumResult(path, cs, entries)),
+                        Err(e) => {
+                            println!("WARNING: {}, using the checksum of the whole file", e);
+                            Ok(InstallationProgress::ChecksumResult(path, cs))
+                        }
                     },
+                    Ok(cs) => Ok(InstallationProgress::ChecksumResult(path, cs)),
+                    Err(e) => Err(e)
+                };
+                match result {
+                    Ok(progress) => Some(((id, progress), State::Finished)),
                     Err(e) => Some(((id, InstallationProgress::Errored(e)), State::Finished))
                 }
             }
@@ -208,4 +275,20 @@ pub enum Progress {
     Finished(String, String),
     Errored,
     Skipped
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_relevant_paths() {
+        assert!(is_sync_relevant("data\\ini\\weapon.ini"));
+        assert!(is_sync_relevant("Data/Scripts/skirmishscripts.scb"));
+        assert!(is_sync_relevant("maps\\map mp fords\\map mp fords.map"));
+        assert!(is_sync_relevant("game.dat"));
+        assert!(is_sync_relevant("lotrbfme2.exe"));
+        assert!(!is_sync_relevant("data\\movies\\intro.vp6"));
+        assert!(!is_sync_relevant("lang\\english\\audio.big"));
+        assert!(!is_sync_relevant("patch\\setup.exe"));
+    }
+}
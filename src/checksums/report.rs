@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use std::path::PathBuf;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use super::{read_checksums_file, read_big_checksums_file};
+
+/// Checksums of a validation: (path, hash) per file and (path, [(entry, hash)]) per BIG archive
+type FileChecksums = Vec<(String, String)>;
+type BigChecksums = Vec<(String, Vec<(String, String)>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Unchanged,
+    Modified,
+    Added,
+    Missing
+}
+
+impl ToString for FileStatus {
+    fn to_string(&self) -> String {
+        match self {
+            FileStatus::Unchanged => "unchanged",
+            FileStatus::Modified => "modified",
+            FileStatus::Added => "added",
+            FileStatus::Missing => "missing"
+        }.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    pub size: Option<u64>,
+    pub hash: Option<String>,
+    pub status: FileStatus,
+    /// Entries of a BIG archive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<EntryReport>
+}
+
+/// A file inside a BIG archive
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryReport {
+    pub name: String,
+    pub hash: Option<String>,
+    pub status: FileStatus
+}
+
+/// Result of a validation run which can be exported for comparison with other players
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub game: String,
+    pub checksum: String,
+    pub sync_fingerprint: String,
+    pub algorithm: String,
+    pub timestamp: String,
+    pub files: Vec<FileReport>,
+    /// Time of the validation, also used for the exported file names
+    #[serde(skip)]
+    pub created: DateTime<Local>
+}
+
+impl ValidationReport {
+
+    /// Creates a report from the (sorted) validation results. Each file's and BIG archive entry's status is
+    /// determined by comparing it to the checksums of the previous validation.
+    pub fn new(game: String, checksum: String, sync_fingerprint: String, install_path: &str,
+               results: &FileChecksums, previous: &FileChecksums,
+               big_results: &BigChecksums, previous_big: &BigChecksums) -> ValidationReport {
+        let created = Local::now();
+        ValidationReport {
+            game,
+            checksum,
+            sync_fingerprint,
+            algorithm: String::from("md5"),
+            timestamp: created.to_rfc3339(),
+            files: compare(install_path, results, previous, big_results, previous_big),
+            created
+        }
+    }
+
+    /// The files and BIG archive entries which aren't unchanged (see `differences`)
+    pub fn differences(&self) -> Vec<String> {
+        differences(&self.files)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// One row per file. The report wide values are repeated in every row, so each
+    /// row can be processed on its own.
+    pub fn to_csv(&self) -> String {
+        let header = "game,checksum,sync_fingerprint,algorithm,timestamp,path,entry,size,hash,status".to_string();
+        let row = |fields: [String; 5]| [
+                self.game.clone(),
+                self.checksum.clone(),
+                self.sync_fingerprint.clone(),
+                self.algorithm.clone(),
+                self.timestamp.clone()
+            ].iter()
+            .chain(fields.iter())
+            .map(|field| csv_escape(field))
+            .collect::<Vec<String>>()
+            .join(",");
+        std::iter::once(header)
+            .chain(self.files.iter().flat_map(|file| std::iter::once(row([
+                    file.path.clone(),
+                    String::new(),
+                    file.size.map(|s| s.to_string()).unwrap_or_default(),
+                    file.hash.clone().unwrap_or_default(),
+                    file.status.to_string()
+                ]))
+                .chain(file.entries.iter().map(|entry| row([
+                    file.path.clone(),
+                    entry.name.clone(),
+                    String::new(),
+                    entry.hash.clone().unwrap_or_default(),
+                    entry.status.to_string()
+                ])))
+                .collect::<Vec<String>>()))
+            .collect::<Vec<String>>()
+            .join("\r\n")
+    }
+
+    /// Writes the report as JSON and CSV into the given directory and returns the path of the JSON file
+    pub fn export(&self, target_dir: PathBuf) -> Result<PathBuf, String> {
+        create_dir_all(&target_dir).map_err(|e| e.to_string())?;
+        let file_name = format!("{}_{}_{}",
+                                self.game.to_lowercase(),
+                                self.checksum,
+                                self.created.format("%Y%m%d-%H%M%S"));
+        let json_path = target_dir.join(format!("{}.json", file_name));
+        let csv_path = target_dir.join(format!("{}.csv", file_name));
+
+        let json = self.to_json()?;
+        println!("Exporting validation report to {:?}...", json_path);
+        File::create(&json_path)
+            .and_then(|mut f| f.write_all(json.as_bytes()))
+            .map_err(|e| e.to_string())?;
+        File::create(&csv_path)
+            .and_then(|mut f| f.write_all(self.to_csv().as_bytes()))
+            .map_err(|e| e.to_string())?;
+        Ok(json_path)
+    }
+}
+
+/// Compares two installations by the checksum files of their last validation, down to the entries of BIG archives.
+/// The differences are those of the first installation, relative to the other one (see `ValidationReport::differences`).
+pub fn diff_installations(install_path: &str, other_path: &str) -> Result<Vec<String>, String> {
+    let results = read_checksums_file(install_path);
+    let other_results = read_checksums_file(other_path);
+    for (path, results) in [(install_path, &results), (other_path, &other_results)].iter() {
+        if results.is_empty() {
+            return Err(format!("{} has not been validated yet", path))
+        }
+    }
+    Ok(differences(&compare(install_path, &results, &other_results,
+                            &read_big_checksums_file(install_path), &read_big_checksums_file(other_path))))
+}
+
+/// The files and BIG archive entries which aren't unchanged, e.g. `INI.big: data\ini\weapon.ini (modified)`.
+/// A modified archive is listed by its changed entries, if they are known.
+fn differences(files: &[FileReport]) -> Vec<String> {
+    files.iter()
+        .filter(|file| file.status != FileStatus::Unchanged)
+        .flat_map(|file| {
+            let entries = file.entries.iter()
+                .filter(|entry| entry.status != FileStatus::Unchanged)
+                .map(|entry| format!("{}: {} ({})", file.path, entry.name, entry.status.to_string()))
+                .collect::<Vec<String>>();
+            match (file.status, entries.is_empty()) {
+                (FileStatus::Modified, false) => entries,
+                _ => vec![format!("{} ({})", file.path, file.status.to_string())]
+            }
+        })
+        .collect()
+}
+
+fn status<T: PartialEq>(current: &T, previous: Option<&T>) -> FileStatus {
+    match previous {
+        Some(previous) if previous == current => FileStatus::Unchanged,
+        Some(_) => FileStatus::Modified,
+        None => FileStatus::Added
+    }
+}
+
+/// File reports for the results compared with the previous ones. If the previous entries of an archive are unknown
+/// (e.g. it was validated by an earlier version), its entries get the archive's status.
+fn compare(install_path: &str, results: &FileChecksums, previous: &FileChecksums,
+           big_results: &BigChecksums, previous_big: &BigChecksums) -> Vec<FileReport> {
+    let previous_map = previous.iter().cloned().collect::<HashMap<String, String>>();
+    let current_map = results.iter().cloned().collect::<HashMap<String, String>>();
+    let big_map = big_results.iter().cloned().collect::<HashMap<String, Vec<(String, String)>>>();
+    let previous_big_map = previous_big.iter().cloned().collect::<HashMap<String, Vec<(String, String)>>>();
+
+    let mut files = results.iter()
+        .map(|(path, hash)| {
+            let file_status = status(hash, previous_map.get(path));
+            let entries = big_map.get(path).map(|entries| {
+                let previous_entries = previous_big_map.get(path)
+                    .filter(|_| file_status != FileStatus::Added)
+                    .map(|entries| entries.iter().cloned().collect::<HashMap<String, String>>());
+                let current_entries = entries.iter().map(|(name, _)| name).collect::<HashSet<&String>>();
+                entries.iter()
+                    .map(|(name, hash)| EntryReport {
+                        name: name.clone(),
+                        hash: Some(hash.clone()),
+                        status: match &previous_entries {
+                            Some(previous_entries) => status(hash, previous_entries.get(name)),
+                            None => file_status
+                        }
+                    })
+                    .chain(previous_entries.iter()
+                        .flat_map(|previous_entries| previous_entries.keys())
+                        .filter(|name| !current_entries.contains(name))
+                        .map(|name| EntryReport { name: name.clone(), hash: None, status: FileStatus::Missing }))
+                    .collect::<Vec<EntryReport>>()
+            }).unwrap_or_default();
+            FileReport {
+                path: path.clone(),
+                size: PathBuf::from(install_path).join(path).metadata().ok().map(|m| m.len()),
+                hash: Some(hash.clone()),
+                status: file_status,
+                entries
+            }
+        })
+        .chain(previous.iter()
+            .filter(|(path, _)| !current_map.contains_key(path))
+            .map(|(path, _)| FileReport {
+                path: path.clone(),
+                size: None,
+                hash: None,
+                status: FileStatus::Missing,
+                entries: vec![]
+            }))
+        .collect::<Vec<FileReport>>();
+    files.sort_by(|f1, f2| f1.path.cmp(&f2.path));
+    for file in files.iter_mut() {
+        file.entries.sort_by(|e1, e2| e1.name.cmp(&e2.name));
+    }
+    files
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::checksums::{write_checksums_file, write_big_checksums_file};
+    use super::*;
+
+    fn checksums(values: &[(&str, &str)]) -> FileChecksums {
+        values.iter().map(|(path, hash)| (path.to_string(), hash.to_string())).collect()
+    }
+
+    fn ini_big(weapon_hash: &str) -> BigChecksums {
+        vec![(String::from("INI.big"), checksums(&[("data\\ini\\armor.ini", "a1"), ("data\\ini\\weapon.ini", weapon_hash)]))]
+    }
+
+    #[test]
+    fn changed_big_entry_is_named() {
+        let previous = checksums(&[("INI.big", "big1"), ("lotrbfme2.exe", "exe")]);
+        let results = checksums(&[("INI.big", "big2"), ("lotrbfme2.exe", "exe")]);
+        let report = ValidationReport::new(String::from("BFME2"), String::from("cs"), String::from("fp"), "",
+                                           &results, &previous, &ini_big("w2"), &ini_big("w1"));
+        assert_eq!(report.differences(), vec!["INI.big: data\\ini\\weapon.ini (modified)"]);
+
+        let ini = &report.files[0];
+        assert_eq!(ini.status, FileStatus::Modified);
+        assert_eq!(ini.entries.iter().map(|e| e.status).collect::<Vec<FileStatus>>(), vec![FileStatus::Unchanged, FileStatus::Modified]);
+        assert!(report.to_json().unwrap().contains("data\\\\ini\\\\weapon.ini"));
+        assert!(report.to_csv().contains(",INI.big,data\\ini\\weapon.ini,,w2,modified"));
+    }
+
+    #[test]
+    fn unknown_previous_entries_take_the_archive_status() {
+        let results = checksums(&[("INI.big", "big1")]);
+        let report = ValidationReport::new(String::from("BFME2"), String::from("cs"), String::from("fp"), "",
+                                           &results, &results, &ini_big("w1"), &vec![]);
+        assert!(report.differences().is_empty());
+        assert!(report.files[0].entries.iter().all(|e| e.status == FileStatus::Unchanged));
+    }
+
+    #[test]
+    fn diff_two_installations() {
+        let dir = TempDir::new().unwrap();
+        let paths = ["a", "b"].iter()
+            .map(|name| dir.path().join(name))
+            .collect::<Vec<PathBuf>>();
+        for (path, weapon_hash) in paths.iter().zip(["w1", "w2"].iter()) {
+            create_dir_all(path).unwrap();
+            let path = path.to_str().unwrap();
+            write_checksums_file(path, checksums(&[("INI.big", weapon_hash), ("lotrbfme2.exe", "exe")])).unwrap();
+            write_big_checksums_file(path, ini_big(weapon_hash)).unwrap();
+        }
+        let (a, b) = (paths[0].to_str().unwrap(), paths[1].to_str().unwrap());
+        assert_eq!(diff_installations(a, b).unwrap(), vec!["INI.big: data\\ini\\weapon.ini (modified)"]);
+        assert!(diff_installations(a, a).unwrap().is_empty());
+        assert!(diff_installations(a, dir.path().join("c").to_str().unwrap()).is_err());
+    }
+}
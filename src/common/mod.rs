@@ -10,56 +10,130 @@ use md5::digest::Output;
 use regex::Regex;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
 use std::hash::{Hash, Hasher};
-use std::io::{Read, Cursor};
+use std::io::Cursor;
 use std::str::from_utf8;
-use winreg::enums::HKEY_LOCAL_MACHINE;
-use winreg::RegKey;
 use base_emoji::try_from_str;
 use crate::installer::InstallerEvent;
-use crate::reg::get_reg_value;
+use crate::reg::backend::registry;
+use crate::reg::elevation::{RegistrationMode, RegistrationReport};
 use crate::checksums::md5sum;
+use crate::options::OptionsIni;
+use crate::maps::{MapIndex, MapLocation};
+use crate::checksums::report::ValidationReport;
 use iced::{
     button, text_input, image
 };
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub enum Game {
-    BFME2,
-    ROTWK
+use serde::{Serialize, Deserialize};
+pub use crate::games::Game;
+
+/// Languages the games were released in, with the values the retail installers write to the registry.
+/// Only languages whose id and locale have been checked against a retail install of that language are offered,
+/// since they are written to the registry as they are.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Language {
+    EnglishUK
 }
 
-impl Game {
-    pub fn all() -> Vec<Game> {
-        vec![Self::BFME2, Self::ROTWK]
+impl Language {
+    pub fn all() -> Vec<Language> {
+        vec![Self::EnglishUK]
+    }
+
+    /// Name of the language as used by the game (`Language` value and data\lang directory)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::EnglishUK => "english"
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Language::EnglishUK => "English UK"
+        }
+    }
+
+    pub fn locale(&self) -> &'static str {
+        match self {
+            Language::EnglishUK => "en_uk"
+        }
+    }
+
+    /// Language id stored as DWORD in the game's version key (e.g. `The Battle for Middle-earth II\1.0`)
+    pub fn id(&self) -> u32 {
+        match self {
+            Language::EnglishUK => 0x13
+        }
+    }
+
+    /// Accepts the locale (e.g. "de") or the display name (e.g. "Deutsch"), case insensitive
+    pub fn parse(value: &str) -> Option<Language> {
+        let value = value.trim().to_lowercase();
+        Self::all().into_iter()
+            .find(|lang| lang.locale() == value || lang.display_name().to_lowercase() == value)
+    }
+
+    /// Like `parse`, with an error listing the known languages
+    pub fn parse_known(value: &str) -> Result<Language, String> {
+        Self::parse(value).ok_or_else(|| format!("Unknown language {} (must be one of: {})", value, Self::all().iter()
+            .map(|lang| format!("{} ({})", lang.display_name(), lang.locale()))
+            .collect::<Vec<String>>()
+            .join(", ")))
     }
 }
 
-impl Hash for Game {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.to_string().hash(state)
+impl Default for Language {
+    fn default() -> Self {
+        Language::EnglishUK
     }
 }
 
-impl Display for Game{
+impl Display for Language {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            Game::BFME2 => "BFME2",
-            Game::ROTWK => "ROTWK"
-        })
+        write!(f, "{}", self.display_name())
     }
 }
 
+/// Identifies an installation in the library
+pub type InstallationId = u32;
+
 #[derive(Debug, Clone)]
 pub enum Message {
-    InstallationEvent(Game, InstallationEvent),
-    AttributeUpdate(Game, InstallationAttribute, String),
-    StartInstallation(Game),
-    StartValidation(Game),
-    InstallationComplete(Game),
-    ValidationComplete(Game, String),
-    Progressed((usize, InstallationProgress))
+    InstallationEvent(InstallationId, InstallationEvent),
+    AttributeUpdate(InstallationId, InstallationAttribute, String),
+    AddInstallation(Game),
+    RemoveInstallation(InstallationId),
+    StartInstallation(InstallationId),
+    StartValidation(InstallationId),
+    StartAdoption(InstallationId),
+    /// Moves the installation to the given path
+    StartRelocation(InstallationId, String),
+    InstallationRelocated(InstallationId, String),
+    DirectoryRemoved(String, Result<(), String>),
+    /// The retail userdata has been taken over, with the resolution from its Options.ini (if it has one)
+    UserdataMigrated(InstallationId, Result<Option<(u32, u32)>, String>),
+    AddProfile(InstallationId, String),
+    /// Switches to the named profile (None for the default profile)
+    SelectProfile(InstallationId, Option<String>),
+    RegistrationModeChanged(InstallationId, RegistrationMode),
+    InstallationComplete(InstallationId),
+    /// The installer was closed after an error
+    InstallationFailed(InstallationId),
+    InstallationActivated(InstallationId),
+    /// Uninstalls the installation, also deleting its userdata if true
+    StartUninstallation(InstallationId, bool),
+    UninstallationComplete(InstallationId, Result<(), String>),
+    ValidationComplete(InstallationId, String, String, ValidationReport),
+    /// Compares the files of the first installation with those of the second one, down to BIG archive entries
+    CompareInstallations(InstallationId, InstallationId),
+    Progressed((usize, InstallationProgress)),
+    SharedMapsDirChanged(String),
+    /// Indexes the maps of all complete installations and the shared map folder again
+    RefreshMaps,
+    /// The maps have been indexed per game. Only the result of the latest refresh (by its number) is shown.
+    MapsIndexed(usize, Vec<(Game, MapIndex)>),
+    /// Copies a map (path relative to the Maps folder) from one location to the other
+    CopyMap(String, MapLocation, MapLocation)
 }
 
 
@@ -68,15 +142,18 @@ pub enum InstallationProgress {
     Started,
     Finished,
     ChecksumResult(String, String),
+    BigChecksumResult(String, String, Vec<(String, String)>),
     Extracting(f32, String),
+    Relocating(f32, String),
     Progressed(u32),
+    Registered(RegistrationReport),
     Errored(String),
     Skipped
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstallationAttribute {
-    Checksum, InstallPath, UserdataPath, ERGC, Resolution, InstallationSource
+    Checksum, SyncFingerprint, InstallPath, UserdataPath, ERGC, Resolution, Language, InstallationSource
 }
 
 
@@ -91,10 +168,12 @@ impl ToString for InstallationAttribute {
     fn to_string(&self) -> String {
         match self {
             Self::Checksum => "Checksum",
+            Self::SyncFingerprint => "Sync Fingerprint",
             Self::InstallPath => "Install Path",
             Self::UserdataPath => "Userdata Directory",
             Self::ERGC => "Activation Code",
             Self::Resolution => "Resolution",
+            Self::Language => "Language",
             Self::InstallationSource => "Install Source"
         }.to_string()
     }
@@ -107,32 +186,48 @@ impl InstallationAttribute {
             InstallationAttribute::UserdataPath, 
             InstallationAttribute::ERGC,
             InstallationAttribute::Resolution,
+            InstallationAttribute::Language,
             InstallationAttribute::InstallationSource]
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Installation {
+    pub id: InstallationId,
     pub game: Game,
     pub path: String,
     userdata_path: String,
     pub checksum: String,
+    pub sync_fingerprint: String,
     pub ergc: String,
     resolution: (u32, u32),
+    pub language: Language,
+    pub registration_mode: RegistrationMode,
     pub install_source: Option<String>,
     pub is_complete: bool,
+    /// Named userdata profiles besides the default one
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// The profile whose userdata the game uses (None for the default profile)
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(skip)]
     pub in_progress: bool,
 }
 
 impl Installation {
     pub(crate) fn defaults(game: Game) -> Installation {
         return Installation {
+            id: 0,
             game,
-            path: format!("C:\\Pgroam Files (x86)\\Electronic Arts\\{}", game),
+            path: format!("C:\\Program Files (x86)\\Electronic Arts\\{}", game),
             userdata_path: String::default(),
             checksum: String::default(),
+            sync_fingerprint: String::default(),
             ergc: String::default(),
             resolution: (1024, 768),
+            language: Language::default(),
+            registration_mode: RegistrationMode::default(),
             install_source: Some(env::current_dir()
                 .expect("Could not retrieve current directory!")
                 .canonicalize()
@@ -140,85 +235,65 @@ impl Installation {
                 .unwrap()
                 .replace("\\\\?\\", ""),),
             is_complete: false,
+            profiles: vec![],
+            profile: None,
             in_progress: false
         }
     }
 
     pub fn load(game: &Game) -> Result<Installation, String> {
-        let game_slug = match game {
-            Game::BFME2 => "The Battle for Middle-earth II",
-            Game::ROTWK => "The Lord of the Rings, The Rise of the Witch-king"
-        };
-        let checksum_result = get_reg_value::<String>(
-            HKEY_LOCAL_MACHINE,
-            &*format!("SOFTWARE\\WOW6432Node\\Electronic Arts\\BFME2 Installer\\{}", game.to_string()),
-            "checksum");
-        let path_result = get_reg_value::<String>(
-            HKEY_LOCAL_MACHINE,
-            &*format!("SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\{}", game_slug),
-            "InstallPath"
-        );
-        let userdata_dir_result = get_reg_value::<String>(
-            HKEY_LOCAL_MACHINE,
-            &*format!("SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\{}", game_slug),
-            "UserDataLeafName"
-        );
+        let layout = &game.definition().registry;
+        let checksum_result = layout.installer_key.value("checksum").read::<String>();
+        // Registrations made before the sync fingerprint was introduced don't have it
+        let sync_fingerprint = layout.installer_key.value("sync_fingerprint").read::<String>().unwrap_or_default();
+        // The installer keeps its own copy of the install path, so changes to the game's keys by other
+        // installers or patches don't go unnoticed (see the registry health check)
+        let path_result = layout.installer_key.value("install_path").read::<String>()
+            .or_else(|_| layout.install_path.read::<String>());
+        let userdata_dir_result = layout.userdata_leaf_name.read::<String>()
+            .or_else(|e| checksum_result.as_ref()
+                .map(|checksum| game.userdata_leaf_name(checksum))
+                .map_err(|_| e));
+        let language = layout.locale.as_ref()
+            .and_then(|locale| locale.read::<String>().ok())
+            .map(|locale| Language::parse_known(&locale)
+                .map_err(|e| format!("{} registry: {}", game, e)))
+            .transpose()?
+            .unwrap_or_default();
         //let data_path_result = Installation::defaults(*game).install_source;
-        let ergc_result = get_reg_value::<String>(
-            HKEY_LOCAL_MACHINE,
-            &*format!("SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\{}\\ergc", game_slug),
-            ""
-        );
+        let ergc_result = layout.ergc.read::<String>();
         println!("Attempted to load data from registry. Found:\n '{:?}' '{:?}' '{:?}'' '{:?}'",
                  checksum_result, path_result, userdata_dir_result, ergc_result);
         match (checksum_result, path_result, userdata_dir_result, ergc_result) {
             (Ok(checksum), Ok(path), Ok(userdata_dir), Ok(ergc)) => {
-                let userdata_path = dirs::home_dir().unwrap()
-                    .join("AppData").join("Roaming")
+                let path = registry().from_registry_path(&path).to_str().unwrap().to_owned();
+                let userdata_path = registry().appdata_path()
                     .join(userdata_dir);
-                let options_path = userdata_path.join("Options.ini");
-                match File::open(&options_path) {
-                    Ok(mut fh) => {
-                        let mut options = String::new();
-                        fh.read_to_string(&mut options);
-                        match options.replace("\r", "").split("\n")
-                            .filter(|s| s.starts_with("Resolution"))
-                            .collect::<Vec<&str>>()
-                            .first() {
-                            Some(line) => {
-                                let v = line.split(" ")
-                                    .map(|s| s.parse::<u32>())
-                                    .filter_map(|i| match i {
-                                    Ok(val) => Some(val),
-                                    Err(_) => None
-                                }).collect::<Vec<u32>>();
-                                match v.len() {
-                                    2 => {
-                                        let resolution = (v[0], v[1]);
-                                        Ok(Installation {
-                                            game: *game,
-                                            checksum,
-                                            path,
-                                            userdata_path: userdata_path.to_str().unwrap().to_owned(),
-                                            ergc,
-                                            resolution,
-                                            install_source: None,
-                                            is_complete: true,
-                                            in_progress: false
-                                        })
-                                    },
-                                    _ => {
-                                        Err(String::from("Error parsing options.ini"))
-                                    }
-                                }
-                            },
-                            None => Err(String::from("Could not find Resolution in options.ini!"))
-                        }
-                    },
-                    _ => {
-                        Err(String::from(format!("Couldn't open '{:?}'", options_path)))
+                let options = OptionsIni::load(&userdata_path)?;
+                for (key, value) in options.values() {
+                    if let Err(e) = value {
+                        println!("WARNING: Options.ini: {}: {}", key, e);
                     }
                 }
+                let resolution = options.resolution()
+                    .ok_or(String::from("Could not find a valid Resolution in Options.ini!"))?;
+                Ok(Installation {
+                    id: 0,
+                    game: *game,
+                    checksum,
+                    sync_fingerprint,
+                    path,
+                    userdata_path: userdata_path.to_str().unwrap().to_owned(),
+                    ergc,
+                    resolution,
+                    language,
+                    registration_mode: RegistrationMode::default(),
+                    install_source: None,
+                    is_complete: true,
+                    profiles: vec![],
+                    profile: None,
+                    in_progress: false
+                })
             }
             e => {
                 Err(String::from(format!("Not matching: Got ({})", [e.0, e.1, e.2, e.3]
@@ -238,22 +313,23 @@ impl Installation {
         self.install_source = other.install_source.clone();
         self.userdata_path = other.userdata_path.clone();
         self.checksum = other.checksum.clone();
+        self.sync_fingerprint = other.sync_fingerprint.clone();
         self.ergc = other.ergc.clone();
         self.resolution = other.resolution.clone();
+        self.language = other.language;
+        self.registration_mode = other.registration_mode;
         self.is_complete = other.is_complete.clone();
     }
 
     pub fn get_userdata_path(&self) -> Option<String> {
-        match self.userdata_path.is_empty() {
+        match self.userdata_path.is_empty() || self.profile.is_some() {
             true => {
                 match self.checksum.is_empty() {
                     true => None,
                     false => {
-                        Some(dirs::home_dir().unwrap()
-                            .join("AppData").join("Roaming")
-                            .join(format!("{}_{}",
-                                          &self.game.to_string().to_lowercase(),
-                                          self.checksum)).to_str().unwrap().to_owned())
+                        Some(registry().appdata_path()
+                            .join(self.userdata_leaf_name())
+                            .to_str().unwrap().to_owned())
                     }
                 }
 
@@ -262,6 +338,27 @@ impl Installation {
         }
     }
 
+    /// Name of the userdata directory of the current profile, e.g. bfme2_<checksum> or bfme2_<checksum>_<profile>
+    pub fn userdata_leaf_name(&self) -> String {
+        let leaf_name = self.game.userdata_leaf_name(&self.checksum);
+        match &self.profile {
+            Some(profile) => format!("{}_{}", leaf_name, profile_slug(profile)),
+            None => leaf_name
+        }
+    }
+
+    /// Userdata directories of the default profile and all named profiles
+    pub fn profile_userdata_paths(&self) -> Vec<String> {
+        std::iter::once(None).chain(self.profiles.iter().cloned().map(Some))
+            .filter_map(|profile| self.with_profile(profile).get_userdata_path())
+            .collect()
+    }
+
+    /// The installation as if the given profile was the current one
+    pub fn with_profile(&self, profile: Option<String>) -> Installation {
+        Installation { profile, ..self.clone() }
+    }
+
     pub fn set_resolution(&mut self, res: String) -> Result<(), String> {
         let err: Result<(), String> = Err(format!("Could not parse resolution string: {}", res));
         match res.split_once("x") {
@@ -284,8 +381,26 @@ impl Installation {
         }
     }
 
-    pub fn get_resolution(&self) -> (u32, u32) {
-        self.resolution.clone()
+    fn options_dir(&self) -> Result<PathBuf, String> {
+        self.get_userdata_path().map(PathBuf::from)
+            .ok_or(String::from("No userdata path (installation has no checksum yet)"))
+    }
+
+    /// The game's Options.ini in the installation's userdata (empty if the game hasn't written it yet)
+    pub fn read_options(&self) -> Result<OptionsIni, String> {
+        Ok(OptionsIni::load_or_default(&self.options_dir()?))
+    }
+
+    pub fn save_options(&self, options: &OptionsIni) -> Result<(), String> {
+        options.save(&self.options_dir()?)
+    }
+
+    /// Writes the installation's resolution to the game's Options.ini. The other settings are edited directly
+    /// (see `read_options`).
+    pub fn write_options(&self) -> Result<(), String> {
+        let mut options = self.read_options()?;
+        options.set_resolution(self.resolution);
+        self.save_options(&options)
     }
 
     pub fn get_resolution_string(&self) -> String {
@@ -297,11 +412,13 @@ impl Installation {
         let ergc = format_ergc(&self.ergc);
         match attr {
             InstallationAttribute::Checksum => self.checksum.clone(),
+            InstallationAttribute::SyncFingerprint => self.sync_fingerprint.clone(),
             InstallationAttribute::InstallPath => self.path.clone(),
             InstallationAttribute::UserdataPath => self.get_userdata_path().unwrap_or(String::from("<will be generated>")),
             //.expect(&format!("Error retrieving userdata path for {}", self.game)),
             InstallationAttribute::ERGC => ergc,
             InstallationAttribute::Resolution => res,
+            InstallationAttribute::Language => self.language.to_string(),
             InstallationAttribute::InstallationSource => self.install_source.as_ref().unwrap_or(&String::from("")).clone()
         }
     }
@@ -311,6 +428,9 @@ impl Installation {
             InstallationAttribute::Checksum => {
                 self.checksum = value;
             },
+            InstallationAttribute::SyncFingerprint => {
+                self.sync_fingerprint = value;
+            },
             InstallationAttribute::InstallPath => {
                 self.path = value;
             },
@@ -326,6 +446,9 @@ impl Installation {
             InstallationAttribute::Resolution => {
                 self.set_resolution(value)?
             }
+            InstallationAttribute::Language => {
+                self.language = Language::parse_known(&value)?
+            }
             InstallationAttribute::InstallationSource => 
             {
                 self.install_source = Some(value);
@@ -336,39 +459,45 @@ impl Installation {
         Ok(())
     }
 
-    pub fn get_full_checksum<'a>(&'a self, other: Option<&'a Installation>) -> Option<String> {
-        match (self.game, other) {
-            (Game::BFME2, _) => Some(self.checksum.clone()),
-            (Game::ROTWK, None) => None,
-            (Game::ROTWK, Some(other_inst)) => {
-                if other_inst.checksum.is_empty() {
+    /// The checksum combined with the base game's installation (if the game is an expansion)
+    pub fn get_full_checksum<'a>(&'a self, base: Option<&'a Installation>) -> Option<String> {
+        self.combine_with_base_game(&self.checksum, base.map(|inst| &inst.checksum))
+    }
+
+    pub fn get_full_sync_fingerprint<'a>(&'a self, base: Option<&'a Installation>) -> Option<String> {
+        if self.sync_fingerprint.is_empty() {
+            return None
+        }
+        self.combine_with_base_game(&self.sync_fingerprint, base.map(|inst| &inst.sync_fingerprint))
+    }
+
+    fn combine_with_base_game(&self, own: &String, base: Option<&String>) -> Option<String> {
+        match (self.game.base_game(), base) {
+            (None, _) => Some(own.clone()),
+            (Some(_), None) => None,
+            (Some(base_game), Some(base_value)) => {
+                if base_value.is_empty() {
                     None
                 } else {
-                    let bfme2_checksum = other_inst.checksum.clone();
                     let full_checksum = md5sum::<Md5, _>(
-                        &mut Cursor::new((bfme2_checksum + &self.checksum).as_bytes()))
-                        .expect("ERROR: Could not create checksum over BFME2 and ROTWK individual checksums");
+                        &mut Cursor::new((base_value.clone() + own).as_bytes()))
+                        .expect(&format!("ERROR: Could not create checksum over {} and {} individual checksums",
+                                         base_game, self.game));
                     let md5_str = format!("{:x}", full_checksum);
                     Some(md5_str)
                 }
             }
         }
-        // if self.checksum == String::default() || (self.game == Game::ROTWK && other.checksum == String::default()) {
-        //     println!("Not returning checksum.. game was {}, own cs: {}, other cs: {}", self.game, self.checksum, other.checksum);
-        //     None
-        // } else {
-        //     match self.game {
-        //         Game::BFME2 => Some(self.checksum.clone()),
-        //         Game::ROTWK => {
-        //             let bfme2_checksum = other.checksum.clone();
-        //             let full_checksum = md5sum::<Md5, _>(
-        //                 &mut Cursor::new((bfme2_checksum + &self.checksum).as_bytes()))
-        //                 .expect("ERROR: Could not create checksum over BFME2 and ROTWK individual checksums");
-        //             let md5_str = format!("{:x}", full_checksum);
-        //             Some(md5_str)
-        //         }
-        //     }
-        // }
+    }
+
+    /// Whether the install path contains a game installation (e.g. by the retail installer) which can be adopted
+    pub fn is_adoption_ready(&self) -> bool {
+        !self.is_complete && PathBuf::from(&self.path).join(&self.game.definition().executable).is_file()
+    }
+
+    /// The ERGC the retail installer (or whatever registered the game last) wrote to the registry
+    pub fn registered_ergc(game: &Game) -> Option<String> {
+        game.definition().registry.ergc.read::<String>().ok().filter(|ergc| !ergc.is_empty())
     }
 
     pub fn is_installation_ready(&self) -> bool {
@@ -376,7 +505,7 @@ impl Installation {
 
         (self.install_source.is_some()) 
         && PathBuf::from(&self.install_source.as_ref().unwrap()).is_dir() 
-        && PathBuf::from(&self.install_source.as_ref().unwrap()).join(format!("{}_0.tar.gz", self.game)).exists()
+        && PathBuf::from(&self.install_source.as_ref().unwrap()).join(format!("{}_0.tar.gz", self.game.definition().archive_prefix)).exists()
         && re.is_match(&format_ergc(&self.ergc))
     }
 }
@@ -403,6 +532,31 @@ impl InstallationUIState {
     }
 }
 
+/// Directory for files created by the installer itself (e.g. validation reports)
+pub fn app_data_dir() -> PathBuf {
+    if cfg!(test) {
+        return test_dir().join("data")
+    }
+    dirs::data_dir()
+        .expect("Could not retrieve application data directory!")
+        .join("BFME2 Installer")
+}
+
+/// Directory for the user's configuration (e.g. registry definition overrides)
+pub fn app_config_dir() -> PathBuf {
+    if cfg!(test) {
+        return test_dir().join("config")
+    }
+    dirs::config_dir()
+        .expect("Could not retrieve configuration directory!")
+        .join("BFME2 Installer")
+}
+
+/// Replaces the user's data and config directories in tests
+fn test_dir() -> PathBuf {
+    env::temp_dir().join(format!("BFME2 Installer test {}", std::process::id()))
+}
+
 pub fn format_ergc(ergc: &str) -> String {
 
     ergc
@@ -420,6 +574,16 @@ pub fn format_ergc(ergc: &str) -> String {
         .collect::<String>()
 }
 
+/// Profile name as used in directory names (letters, digits, - and _)
+pub fn profile_slug(name: &str) -> String {
+    name.trim().chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            true => c.to_ascii_lowercase(),
+            false => '_'
+        })
+        .collect()
+}
+
 pub fn to_breakable(value: String) -> String {
     let result = value.chars()
         .enumerate()
@@ -441,4 +605,36 @@ pub fn str_to_emoji_hash(value: String) -> Result<String, String> {
         Err(e) => Err(e)
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    #[test]
+    fn languages_parse_by_locale_and_name() {
+        for language in Language::all() {
+            assert_eq!(Language::parse(language.locale()), Some(language));
+            assert_eq!(Language::parse(&language.display_name().to_uppercase()), Some(language));
+        }
+        assert_eq!(Language::parse(" en_UK "), Some(Language::EnglishUK));
+        assert!(Language::parse_known("klingon").is_err());
+        // Languages whose registry values haven't been checked against a retail install aren't offered
+        assert!(Language::parse_known("de").is_err());
+    }
+
+    #[test]
+    fn language_values_are_unique() {
+        let languages = Language::all();
+        assert_eq!(languages.iter().map(|l| l.locale()).collect::<HashSet<_>>().len(), languages.len());
+        assert_eq!(languages.iter().map(|l| l.id()).collect::<HashSet<_>>().len(), languages.len());
+    }
+
+    #[test]
+    fn unknown_language_is_an_error() {
+        let mut installation = Installation::defaults(Game::from_id("BFME2").unwrap());
+        installation.set_attribute(&InstallationAttribute::Language, String::from("English UK")).unwrap();
+        assert!(installation.set_attribute(&InstallationAttribute::Language, String::from("xx")).is_err());
+        assert_eq!(installation.language, Language::EnglishUK);
+    }
+}
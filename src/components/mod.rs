@@ -3,15 +3,24 @@ use std::path::PathBuf;
 use iced::{text_input, button, image, Subscription};
 use iced::{
     Command, Column, Element, Text, Row, Length, 
-    VerticalAlignment, TextInput, Button, Container, Align,
+    VerticalAlignment, TextInput, Button, Container, Align, Checkbox,
     Color, Background, Vector, Space, HorizontalAlignment};
 use regex::Regex;
 use regex::internal::Inst;
 use crate::common::InstallationProgress;
 
-use super::common::{Installation, Message, Game, InstallationAttribute, to_breakable, format_ergc};
+use super::common::{Installation, InstallationId, Message, Game, InstallationAttribute, to_breakable, format_ergc, profile_slug, app_data_dir};
+use super::checksums::report::ValidationReport;
 use super::installer::{InstallerEvent, Installer};
 use super::checksums::{md5sum};
+use super::userdata::{self, ScannedFile};
+use super::userdata::backup::UserdataBackup;
+use super::reg;
+use super::relocate;
+use super::options;
+use super::maps::{IndexedLocation, MapIndex, MapLocation};
+use super::reg::health::Drift;
+use super::reg::elevation::{RegistrationMode, RegistrationReport};
 use md5::Md5;
 use std::io::{Cursor};
 use blockies::Ethereum;
@@ -26,6 +35,7 @@ struct Layout {
 
 #[derive(Debug, Clone)]
 pub struct InstallationView {
+    pub id: InstallationId,
     pub game: Game,
     //installation: Installation,
     bfme2_checksum: Option<String>,
@@ -36,10 +46,19 @@ pub struct InstallationView {
     layout: Layout,
     attributes: Vec<AttributeView>,
     editing: Option<InstallationAttribute>,
-    compat_views: (CompatibilityView, CompatibilityView),
+    compat_views: (CompatibilityView, CompatibilityView, CompatibilityView),
     //installer: Option<Installer>,
     install_button: button::State,
-    validate_button: button::State
+    validate_button: button::State,
+    remove_button: button::State,
+    taint_view: TaintView,
+    report_view: ReportView,
+    backup_view: UserdataBackupView,
+    registry_view: RegistryView,
+    profile_view: ProfileView,
+    options_view: OptionsView,
+    relocate_view: RelocateView,
+    uninstall_view: UninstallView
 }
 
 
@@ -73,7 +92,7 @@ impl button::StyleSheet for AttributeButtonStyle {
 
 
 impl InstallationView {
-    pub fn new(game: Game) -> Self {
+    pub fn new(id: InstallationId, game: Game) -> Self {
         let layout = Layout {
             header_size: 24,
             title_size: 20,
@@ -81,6 +100,7 @@ impl InstallationView {
             text_height: 36
         };
         Self{
+            id,
             game,
             bfme2_checksum: None,
             resolution_input: text_input::State::default(),
@@ -89,14 +109,24 @@ impl InstallationView {
             compat_image_ergc: image::viewer::State::default(),
             layout: layout.clone(),
             attributes: InstallationAttribute::all().into_iter()
-                .map(|attr| AttributeView::new(attr, game, layout.clone()))
+                .map(|attr| AttributeView::new(attr, id, layout.clone()))
                 .collect::<Vec<AttributeView>>(),
             editing: None,
             compat_views: (
-                CompatibilityView::new(InstallationAttribute::Checksum, game, layout.clone()),
-                CompatibilityView::new(InstallationAttribute::ERGC, game, layout.clone())),
+                CompatibilityView::new(InstallationAttribute::Checksum, id, layout.clone()),
+                CompatibilityView::new(InstallationAttribute::SyncFingerprint, id, layout.clone()),
+                CompatibilityView::new(InstallationAttribute::ERGC, id, layout.clone())),
             install_button: button::State::default(),
-            validate_button: button::State::default()
+            validate_button: button::State::default(),
+            remove_button: button::State::default(),
+            taint_view: TaintView::new(id, layout.clone()),
+            report_view: ReportView::new(id, layout.clone()),
+            backup_view: UserdataBackupView::new(id, game, layout.clone()),
+            registry_view: RegistryView::new(id, layout.clone()),
+            profile_view: ProfileView::new(id, layout.clone()),
+            options_view: OptionsView::new(id, layout.clone()),
+            relocate_view: RelocateView::new(id, layout.clone()),
+            uninstall_view: UninstallView::new(id, layout.clone())
         }
     }
 
@@ -109,20 +139,242 @@ impl InstallationView {
                     self.editing = None;
                 }
             }
+            InstallationEvent::ScanUserdata => {
+                if !self.taint_view.busy {
+                    return self.taint_view.scan(installation)
+                }
+            }
+            InstallationEvent::UserdataScanned(result) => {
+                self.taint_view.busy = false;
+                self.taint_view.result = Some(result);
+            }
+            InstallationEvent::ExportRegistration => {
+                self.registry_view.export_result = Some(reg::export::export_installation(installation));
+            }
+            InstallationEvent::CheckRegistry => {
+                if !self.registry_view.busy {
+                    self.registry_view.busy = true;
+                    let installation = installation.clone();
+                    let id = installation.id;
+                    return Command::perform(async move { reg::health::check(&installation) },
+                                            move |result| Message::InstallationEvent(id, InstallationEvent::RegistryChecked(result)))
+                }
+            }
+            InstallationEvent::RepairRegistry => {
+                if let (false, Some(Ok(drifts))) = (self.registry_view.busy, &self.registry_view.health_result) {
+                    self.registry_view.busy = true;
+                    let drifts = drifts.clone();
+                    let installation = installation.clone();
+                    let id = installation.id;
+                    return Command::perform(async move {
+                        reg::health::repair(&drifts).and_then(|_| reg::health::check(&installation))
+                    }, move |result| Message::InstallationEvent(id, InstallationEvent::RegistryChecked(result)))
+                }
+            }
+            InstallationEvent::RegistryChecked(result) => {
+                self.registry_view.busy = false;
+                self.registry_view.health_result = Some(result);
+            }
+            InstallationEvent::Activate => {
+                if !installation.in_progress && !self.registry_view.busy {
+                    self.registry_view.busy = true;
+                    let installation = installation.clone();
+                    let id = installation.id;
+                    return Command::perform(async move { Installer::activate(&installation) },
+                                            move |result| Message::InstallationEvent(id, InstallationEvent::Activated(result)))
+                }
+            }
+            InstallationEvent::Activated(result) => {
+                self.registry_view.busy = false;
+                let is_ok = result.is_ok();
+                self.registry_view.activate_result = Some(result);
+                if is_ok {
+                    let id = installation.id;
+                    return Command::perform(async move { id }, Message::InstallationActivated)
+                }
+            }
+            InstallationEvent::RestoreRegistry => {
+                self.registry_view.restore_result = Some(reg::backup::restore(&installation.game, &installation.checksum));
+            }
+            InstallationEvent::QuarantineTainted => {
+                if let (false, Some(Ok(files))) = (self.taint_view.busy, &self.taint_view.result) {
+                    let tainted: Vec<ScannedFile> = files.iter().filter(|f| f.tainted).cloned().collect();
+                    self.taint_view.busy = true;
+                    let installation = installation.clone();
+                    let id = installation.id;
+                    return Command::perform(async move {
+                        userdata::quarantine(&installation, &tainted).map(Some)
+                    }, move |result| Message::InstallationEvent(id, InstallationEvent::QuarantineDone(result)))
+                }
+            }
+            InstallationEvent::RestoreQuarantined => {
+                if let (false, Some(path)) = (self.taint_view.busy, self.taint_view.quarantine.clone()) {
+                    self.taint_view.busy = true;
+                    let installation = installation.clone();
+                    let id = installation.id;
+                    return Command::perform(async move {
+                        userdata::restore_quarantined(&installation, &path).map(|_| None)
+                    }, move |result| Message::InstallationEvent(id, InstallationEvent::QuarantineDone(result)))
+                }
+            }
+            InstallationEvent::QuarantineDone(result) => {
+                self.taint_view.busy = false;
+                match result {
+                    Ok(path) => {
+                        self.taint_view.quarantine = path;
+                        self.taint_view.error = None;
+                        return self.taint_view.scan(installation)
+                    },
+                    Err(msg) => self.taint_view.error = Some(msg)
+                }
+            }
+            InstallationEvent::ExportReport => {
+                if let Some(report) = &self.report_view.report {
+                    self.report_view.result = Some(report.export(app_data_dir().join("reports")));
+                }
+            }
+            InstallationEvent::ComparisonDone(peer, result) => {
+                self.report_view.comparison = Some((peer, result));
+            }
+            InstallationEvent::BackupUserdata => {
+                if installation.in_progress || self.backup_view.busy {
+                    return Command::none()
+                }
+                self.backup_view.busy = true;
+                let installation = installation.clone();
+                let id = installation.id;
+                return Command::perform(async move {
+                    userdata::backup::create(&installation)
+                        .map(|path| format!("Userdata saved to {}", path.to_str().unwrap()))
+                }, move |result| Message::InstallationEvent(id, InstallationEvent::UserdataBackupDone(result)))
+            }
+            InstallationEvent::RestoreUserdata(path) => {
+                if installation.in_progress || self.backup_view.busy {
+                    return Command::none()
+                }
+                match self.backup_view.backups.iter().find(|backup| backup.path == path).cloned() {
+                    Some(backup) => {
+                        self.backup_view.busy = true;
+                        let installation = installation.clone();
+                        let id = installation.id;
+                        return Command::perform(async move {
+                            userdata::backup::restore(&backup, &installation)
+                                .map(|_| format!("Restored the backup of {} from {}", backup.source, backup.created))
+                        }, move |result| Message::InstallationEvent(id, InstallationEvent::UserdataBackupDone(result)))
+                    },
+                    None => self.backup_view.result = Some(Err(format!("{:?} doesn't exist anymore", path)))
+                }
+            }
+            InstallationEvent::UserdataBackupDone(result) => {
+                self.backup_view.busy = false;
+                self.backup_view.result = Some(result);
+                self.backup_view.refresh();
+            }
+            InstallationEvent::ProfileNameChanged(name) => {
+                self.profile_view.name = name;
+                self.profile_view.message = None;
+            }
+            InstallationEvent::AddProfile => {
+                let name = self.profile_view.name.trim().to_string();
+                let slug = profile_slug(&name);
+                if slug.chars().all(|c| c == '_') {
+                    self.profile_view.message = Some(String::from("Can't add the profile: the name needs at least one letter or digit"));
+                } else if installation.profiles.iter().any(|profile| profile_slug(profile) == slug) {
+                    self.profile_view.message = Some(format!("Can't add the profile: there is already a profile named {}", name));
+                } else {
+                    self.profile_view.name = String::new();
+                    let id = installation.id;
+                    return Command::perform(async move { (id, name) }, |(id, name)| Message::AddProfile(id, name))
+                }
+            }
+            InstallationEvent::LoadOptions => {
+                self.options_view.load(installation);
+            }
+            InstallationEvent::OptionChanged(key, value) => {
+                if let Some((_, current, _)) = self.options_view.settings.iter_mut().find(|(k, _, _)| *k == key) {
+                    *current = value;
+                }
+            }
+            InstallationEvent::SaveOptions => {
+                if !installation.in_progress {
+                    self.options_view.save(installation);
+                }
+            }
+            InstallationEvent::RelocationTargetChanged(target) => {
+                self.relocate_view.target = target;
+                self.relocate_view.error = None;
+            }
+            InstallationEvent::Relocate => {
+                if installation.in_progress {
+                    return Command::none()
+                }
+                let target = self.relocate_view.target.trim().to_string();
+                match relocate::check_target(&installation.path, &target) {
+                    Ok(()) => {
+                        let id = installation.id;
+                        return Command::perform(async move { (id, target) }, |(id, target)| Message::StartRelocation(id, target))
+                    },
+                    Err(msg) => self.relocate_view.error = Some(msg)
+                }
+            }
+            InstallationEvent::RemoveUserdataToggled(remove_userdata) => {
+                self.uninstall_view.remove_userdata = remove_userdata;
+            }
+            InstallationEvent::Uninstall => {
+                if !installation.in_progress {
+                    self.uninstall_view.confirming = true;
+                    self.uninstall_view.error = None;
+                }
+            }
+            InstallationEvent::CancelUninstall => {
+                self.uninstall_view.confirming = false;
+            }
+            InstallationEvent::ConfirmUninstall => {
+                self.uninstall_view.confirming = false;
+                if !installation.in_progress {
+                    let id = installation.id;
+                    let remove_userdata = self.uninstall_view.remove_userdata;
+                    return Command::perform(async move { (id, remove_userdata) },
+                                            |(id, remove_userdata)| Message::StartUninstallation(id, remove_userdata))
+                }
+            }
             _ => {}
         }
         Command::none()
     }
 
+    /// Shows the outcome of a profile switch or of moving the profiles' userdata along with a new checksum
+    pub fn set_profile_message(&mut self, msg: String) {
+        self.profile_view.message = Some(msg);
+    }
+
+    /// Reads the list of userdata backups again (e.g. after an installation backed up the userdata it replaced)
+    pub fn refresh_backups(&mut self) {
+        self.backup_view.refresh();
+    }
+
+    pub fn set_uninstall_error(&mut self, msg: String) {
+        self.uninstall_view.error = Some(msg);
+    }
+
+    /// Keeps the report of the last validation for exporting
+    pub fn set_validation_report(&mut self, report: ValidationReport) {
+        self.report_view.report = Some(report);
+        self.report_view.result = None;
+    }
+
     pub fn loose_focus(&mut self) {
         self.editing = None
     }
 
-    pub fn render<'a>(&'a mut self, installation: &'a Installation, installer_view: Option<Element<'a, Message>>, other_installation: Option<&Installation>) -> Element<'a, Message> {
+    pub fn render<'a>(&'a mut self, installation: &'a Installation, installer_view: Option<Element<'a, Message>>, other_installation: Option<&Installation>, is_active: bool, peers: &[InstallationId]) -> Element<'a, Message> {
         let Installation{game, ergc, ..} = installation;
         
         let mut col = Column::new()
-            .push(Text::new(game.to_string())
+            .push(Text::new(match is_active {
+                    true => format!("{} (active)", game),
+                    false => game.to_string()
+                })
                 .size(self.layout.header_size)
                 .vertical_alignment(VerticalAlignment::Center)
                 .height(Length::Units(self.layout.text_height+4)));
@@ -166,24 +418,46 @@ impl InstallationView {
                         .horizontal_alignment(HorizontalAlignment::Center))
                 .width(Length::FillPortion(1));
             if ! installation.in_progress && installation.is_installation_ready() {
-                install_button = install_button.on_press(Message::StartInstallation(self.game));
+                install_button = install_button.on_press(Message::StartInstallation(self.id));
             }
 
+            // Installations which aren't installed yet can take over an existing installation at their path instead
             let mut validate_button = Button::new(
                     &mut self.validate_button, 
-                    Text::new("Validate")
+                    Text::new(match installation.is_complete {true => "Validate", false => "Adopt existing"})
                         .horizontal_alignment(HorizontalAlignment::Center))
                 .width(Length::FillPortion(1));
             if ! installation.in_progress && installation.is_complete {
-                validate_button = validate_button.on_press(Message::StartValidation(self.game))
+                validate_button = validate_button.on_press(Message::StartValidation(self.id))
+            } else if ! installation.in_progress && installation.is_adoption_ready() {
+                validate_button = validate_button.on_press(Message::StartAdoption(self.id))
             }
             
+            let id = self.id;
+            let mut remove_button = Button::new(
+                    &mut self.remove_button,
+                    Text::new("Remove from list")
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::FillPortion(1));
+            if ! installation.in_progress {
+                remove_button = remove_button.on_press(Message::RemoveInstallation(self.id))
+            }
+
             col = col.push(Row::new().spacing(10)
                 .push(install_button)
-                .push(validate_button));
+                .push(validate_button)
+                .push(remove_button))
+                .push(Checkbox::new(installation.registration_mode == RegistrationMode::User,
+                                    "Register without admin rights",
+                                    move |checked| Message::RegistrationModeChanged(id, match checked {
+                                        true => RegistrationMode::User,
+                                        false => RegistrationMode::Admin
+                                    }))
+                    .text_size(self.layout.value_size));
             
             if installation.is_complete {
                 let full_checksum = installation.get_full_checksum(other_installation);
+                let sync_fingerprint = installation.get_full_sync_fingerprint(other_installation);
 
                 col = col.push(Space::new(Length::Fill, Length::Units(60)))
                     .push(Row::new()
@@ -200,6 +474,13 @@ impl InstallationView {
                                     .size(self.layout.value_size)
                                     .height(Length::Units(self.layout.text_height))
                                     .vertical_alignment(VerticalAlignment::Center)))
+                            .push(Row::new().push(self.compat_views.1.view(sync_fingerprint))))
+                        .push(Column::new().width(Length::FillPortion(1)).align_items(Align::Center)
+                            .push(Row::new()
+                                .push(Text::new("All files")
+                                    .size(self.layout.value_size)
+                                    .height(Length::Units(self.layout.text_height))
+                                    .vertical_alignment(VerticalAlignment::Center)))
                             .push(Row::new().push(self.compat_views.0.view(full_checksum))))
                         .push(Column::new().width(Length::FillPortion(1)).align_items(Align::Center)
                             .push(Row::new()
@@ -207,7 +488,23 @@ impl InstallationView {
                                     .size(self.layout.value_size)
                                     .height(Length::Units(self.layout.text_height))
                                     .vertical_alignment(VerticalAlignment::Center)))
-                            .push(Row::new().push(self.compat_views.1.view(ergc_checksum)))))
+                            .push(Row::new().push(self.compat_views.2.view(ergc_checksum)))))
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.report_view.view(peers))
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.taint_view.view())
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.backup_view.view())
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.profile_view.view(installation))
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.options_view.view())
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.registry_view.view(reg::backup::has_backup(game, &installation.checksum)))
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.relocate_view.view())
+                    .push(Space::new(Length::Fill, Length::Units(30)))
+                    .push(self.uninstall_view.view(installation))
             };
             col
         };
@@ -216,7 +513,7 @@ impl InstallationView {
     }
 
     fn can_edit(attr: InstallationAttribute, installation: &Installation) -> bool {
-        let mut editables = vec![InstallationAttribute::ERGC, InstallationAttribute::Resolution, InstallationAttribute::InstallationSource];
+        let mut editables = vec![InstallationAttribute::ERGC, InstallationAttribute::Resolution, InstallationAttribute::Language, InstallationAttribute::InstallationSource];
         if !installation.is_complete {
             editables.push(InstallationAttribute::InstallPath);
         }
@@ -232,7 +529,7 @@ impl InstallationView {
 #[derive(Debug, Clone)]
 struct AttributeView {
     id: InstallationAttribute,
-    game: Game,
+    installation_id: InstallationId,
     title: String,
     layout: Layout,
     input: text_input::State,
@@ -240,10 +537,10 @@ struct AttributeView {
 }
 
 impl AttributeView {
-    fn new(id: InstallationAttribute, game: Game, layout: Layout) -> Self {
+    fn new(id: InstallationAttribute, installation_id: InstallationId, layout: Layout) -> Self {
         Self {
             id,
-            game,
+            installation_id,
             title: id.to_string(),
             layout,
             input: text_input::State::default(),
@@ -252,7 +549,7 @@ impl AttributeView {
     }
 
     fn view<'a>(&mut self, value: String, editing: bool, editable: bool) -> Element<Message> {
-        let game = self.game;
+        let installation_id = self.installation_id;
         let id = self.id;
 
         if editing {
@@ -268,7 +565,7 @@ impl AttributeView {
         .push::<Element<Message>>(match editing {
             true => Container::new(
                     TextInput::new(&mut self.input, &self.title, &value, 
-                        move |data| Message::AttributeUpdate(game, id, data)))
+                        move |data| Message::AttributeUpdate(installation_id, id, data)))
                 .height(Length::Units(self.layout.text_height))
                 .width(Length::Fill)
                 .center_y()
@@ -281,7 +578,7 @@ impl AttributeView {
                     .height(Length::Units(self.layout.text_height))
                     .width(Length::Fill)
                     .padding(0)
-                    .on_press(Message::InstallationEvent(self.game, InstallationEvent::AttributeClicked(self.id))).into()
+                    .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::AttributeClicked(self.id))).into()
         }).into()
             
     }
@@ -296,15 +593,15 @@ impl PartialEq for AttributeView {
 #[derive(Debug, Clone)]
 struct CompatibilityView {
     id: InstallationAttribute,
-    game: Game,
+    installation_id: InstallationId,
     layout: Layout,
     image_state: image::viewer::State
 }
 
 impl CompatibilityView {
 
-    fn new(id: InstallationAttribute, game: Game, layout: Layout) -> Self{
-        Self {id, game, layout, image_state: image::viewer::State::default()}
+    fn new(id: InstallationAttribute, installation_id: InstallationId, layout: Layout) -> Self{
+        Self {id, installation_id, layout, image_state: image::viewer::State::default()}
     }
 
     fn view(&mut self, value: Option<String>) -> Element<Message>{
@@ -329,11 +626,854 @@ impl CompatibilityView {
     }
 }
 
+#[derive(Debug, Clone)]
+struct TaintView {
+    installation_id: InstallationId,
+    layout: Layout,
+    result: Option<Result<Vec<ScannedFile>, String>>,
+    /// The files quarantined last, they can be restored until the view is closed
+    quarantine: Option<PathBuf>,
+    error: Option<String>,
+    busy: bool,
+    scan_button: button::State,
+    quarantine_button: button::State,
+    restore_button: button::State
+}
+
+impl TaintView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            result: None,
+            quarantine: None,
+            error: None,
+            busy: false,
+            scan_button: button::State::default(),
+            quarantine_button: button::State::default(),
+            restore_button: button::State::default()
+        }
+    }
+
+    fn scan(&mut self, installation: &Installation) -> Command<Message> {
+        self.busy = true;
+        let installation = installation.clone();
+        let id = installation.id;
+        Command::perform(async move { userdata::scan(&installation) },
+                         move |result| Message::InstallationEvent(id, InstallationEvent::UserdataScanned(result)))
+    }
+
+    fn view(&mut self) -> Element<Message> {
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Userdata")
+                .size(self.layout.title_size)
+                .width(Length::Fill)
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .height(Length::Units(self.layout.text_height)))
+            .push(Button::new(&mut self.scan_button, Text::new("Scan heroes and profiles")
+                    .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::Fill)
+                .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::ScanUserdata)));
+
+        if self.busy {
+            return col.push(Text::new("Scanning...").size(self.layout.value_size)).into()
+        }
+        if let Some(msg) = &self.error {
+            col = col.push(Text::new(to_breakable(msg.clone())).size(self.layout.value_size));
+        }
+        if let Some(path) = &self.quarantine {
+            col = col
+                .push(Text::new(to_breakable(format!("Quarantined files moved to {}", path.to_string_lossy())))
+                    .size(self.layout.value_size))
+                .push(Button::new(&mut self.restore_button, Text::new("Restore quarantined files")
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::Fill)
+                    .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::RestoreQuarantined)));
+        }
+        match &self.result {
+            Some(Ok(files)) => {
+                let tainted = files.iter().filter(|f| f.tainted).collect::<Vec<&ScannedFile>>();
+                col = col.push(Text::new(format!("{} files checked, {} created with a different game version",
+                                                 files.len(), tainted.len()))
+                    .size(self.layout.value_size));
+                for file in &tainted {
+                    col = col.push(Text::new(to_breakable(format!("{} (created with {})", file.path, file.origin)))
+                        .size(self.layout.value_size));
+                }
+                if !tainted.is_empty() {
+                    col = col.push(Button::new(&mut self.quarantine_button, Text::new("Quarantine")
+                            .horizontal_alignment(HorizontalAlignment::Center))
+                        .width(Length::Fill)
+                        .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::QuarantineTainted)));
+                }
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Scan failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ReportView {
+    installation_id: InstallationId,
+    layout: Layout,
+    report: Option<ValidationReport>,
+    result: Option<Result<PathBuf, String>>,
+    /// Differences to another installation of the game (see `checksums::report::diff_installations`)
+    comparison: Option<(InstallationId, Result<Vec<String>, String>)>,
+    export_button: button::State,
+    compare_buttons: Vec<button::State>
+}
+
+impl ReportView {
+
+    /// Differences listed at most, the full list is in the exported report
+    const MAX_DIFFERENCES: usize = 20;
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            report: None,
+            result: None,
+            comparison: None,
+            export_button: button::State::default(),
+            compare_buttons: vec![]
+        }
+    }
+
+    fn differences_view<'a>(mut col: Column<'a, Message>, differences: &[String], value_size: u16) -> Column<'a, Message> {
+        for difference in differences.iter().take(Self::MAX_DIFFERENCES) {
+            col = col.push(Text::new(to_breakable(difference.clone())).size(value_size));
+        }
+        if differences.len() > Self::MAX_DIFFERENCES {
+            col = col.push(Text::new(format!("... and {} more", differences.len() - Self::MAX_DIFFERENCES)).size(value_size));
+        }
+        col
+    }
+
+    /// `peers` are the other complete installations of the game, which the installation can be compared with
+    fn view(&mut self, peers: &[InstallationId]) -> Element<Message> {
+        let mut export_button = Button::new(&mut self.export_button, Text::new("Export validation report")
+                .horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::Fill);
+        if self.report.is_some() {
+            export_button = export_button.on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::ExportReport));
+        }
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Validation report")
+                .size(self.layout.title_size)
+                .width(Length::Fill)
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .height(Length::Units(self.layout.text_height)))
+            .push(export_button);
+
+        match (&self.report, &self.result) {
+            (_, Some(Ok(path))) => {
+                col = col.push(Text::new(to_breakable(format!("Saved as {} and .csv", path.to_str().unwrap())))
+                    .size(self.layout.value_size));
+            },
+            (_, Some(Err(msg))) => {
+                col = col.push(Text::new(format!("Export failed: {}", msg)).size(self.layout.value_size));
+            },
+            (Some(report), None) => {
+                let differences = report.differences();
+                col = col.push(Text::new(format!("Validated {} ({} files, {} changes since the previous validation)",
+                                                 report.created.format("%Y-%m-%d %H:%M:%S"), report.files.len(), differences.len()))
+                    .size(self.layout.value_size));
+                col = Self::differences_view(col, &differences, self.layout.value_size);
+            },
+            (None, None) => {
+                col = col.push(Text::new("Validate the installation to create a report").size(self.layout.value_size));
+            }
+        }
+
+        self.compare_buttons.resize(peers.len(), button::State::default());
+        let mut compare_row = Row::new().spacing(10);
+        for (peer, state) in peers.iter().zip(self.compare_buttons.iter_mut()) {
+            compare_row = compare_row.push(Button::new(state, Text::new(format!("Compare with #{}", peer))
+                    .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::FillPortion(1))
+                .on_press(Message::CompareInstallations(self.installation_id, *peer)));
+        }
+        col = col.push(compare_row);
+        match &self.comparison {
+            Some((peer, Ok(differences))) => {
+                col = col.push(Text::new(match differences.is_empty() {
+                    true => format!("Same files as #{}", peer),
+                    false => format!("{} differences to #{}:", differences.len(), peer)
+                }).size(self.layout.value_size));
+                col = Self::differences_view(col, differences, self.layout.value_size);
+            },
+            Some((peer, Err(msg))) => {
+                col = col.push(Text::new(format!("Can't compare with #{}: {}", peer, msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct UserdataBackupView {
+    installation_id: InstallationId,
+    game: Game,
+    layout: Layout,
+    /// The game's backups, read when the view is created and after backing up or restoring
+    backups: Vec<UserdataBackup>,
+    /// A backup or restore is running
+    busy: bool,
+    result: Option<Result<String, String>>,
+    backup_button: button::State,
+    restore_buttons: Vec<button::State>
+}
+
+impl UserdataBackupView {
+
+    fn new(installation_id: InstallationId, game: Game, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            game,
+            layout,
+            backups: userdata::backup::list(&game),
+            busy: false,
+            result: None,
+            backup_button: button::State::default(),
+            restore_buttons: vec![]
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.backups = userdata::backup::list(&self.game);
+    }
+
+    fn view(&mut self) -> Element<Message> {
+        self.restore_buttons.resize(self.backups.len(), button::State::default());
+        let installation_id = self.installation_id;
+        let busy = self.busy;
+
+        let mut backup_button = Button::new(&mut self.backup_button, Text::new(match busy {
+                true => "Working...",
+                false => "Back up userdata"
+            }).horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::Fill);
+        if !busy {
+            backup_button = backup_button.on_press(Message::InstallationEvent(installation_id, InstallationEvent::BackupUserdata));
+        }
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Userdata backups")
+                .size(self.layout.title_size)
+                .width(Length::Fill)
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .height(Length::Units(self.layout.text_height)))
+            .push(backup_button);
+
+        match &self.result {
+            Some(Ok(msg)) => {
+                col = col.push(Text::new(to_breakable(msg.clone())).size(self.layout.value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+
+        // Backups of the game's other installations can be restored here as well
+        for (backup, state) in self.backups.iter().zip(self.restore_buttons.iter_mut()) {
+            let mut restore_button = Button::new(state, Text::new("Restore").size(self.layout.value_size));
+            if !busy {
+                restore_button = restore_button.on_press(Message::InstallationEvent(installation_id, InstallationEvent::RestoreUserdata(backup.path.clone())));
+            }
+            col = col.push(Row::new().spacing(10)
+                .push(Text::new(to_breakable(format!("{} ({})", backup.source, backup.created.format("%Y-%m-%d %H:%M:%S"))))
+                    .size(self.layout.value_size)
+                    .width(Length::FillPortion(3)))
+                .push(restore_button));
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RegistryView {
+    installation_id: InstallationId,
+    layout: Layout,
+    export_result: Option<Result<PathBuf, String>>,
+    restore_result: Option<Result<(), String>>,
+    health_result: Option<Result<Vec<Drift>, String>>,
+    activate_result: Option<Result<RegistrationReport, String>>,
+    /// A registry check, repair or activation is running
+    busy: bool,
+    activate_button: button::State,
+    export_button: button::State,
+    restore_button: button::State,
+    check_button: button::State,
+    repair_button: button::State
+}
+
+impl RegistryView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            export_result: None,
+            restore_result: None,
+            health_result: None,
+            activate_result: None,
+            busy: false,
+            activate_button: button::State::default(),
+            export_button: button::State::default(),
+            restore_button: button::State::default(),
+            check_button: button::State::default(),
+            repair_button: button::State::default()
+        }
+    }
+
+    fn view(&mut self, has_backup: bool) -> Element<Message> {
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Registry")
+                .size(self.layout.title_size)
+                .width(Length::Fill)
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .height(Length::Units(self.layout.text_height)))
+            .push(Button::new(&mut self.activate_button, Text::new("Make active")
+                    .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::Fill)
+                .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::Activate)));
+
+        match &self.activate_result {
+            _ if self.busy => {}
+            Some(Ok(report)) if report.is_complete() => {
+                col = col.push(Text::new("Installation is active").size(self.layout.value_size));
+            },
+            Some(Ok(report)) => {
+                col = col.push(Text::new(to_breakable(format!("Installation is active for the current user. Import {} as administrator to activate it for all users.",
+                                                              report.reg_file.as_ref().and_then(|p| p.to_str()).unwrap_or("the exported .reg file"))))
+                    .size(self.layout.value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Activation failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+
+        col = col.push(Button::new(&mut self.check_button, Text::new("Check registry")
+                    .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::Fill)
+                .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::CheckRegistry)));
+
+        match &self.health_result {
+            _ if self.busy => {
+                col = col.push(Text::new("Please wait...").size(self.layout.value_size));
+            },
+            Some(Ok(drifts)) if drifts.is_empty() => {
+                col = col.push(Text::new("All registry values are as expected").size(self.layout.value_size));
+            },
+            Some(Ok(drifts)) => {
+                col = col.push(Text::new(format!("{} registry values differ from the expected values", drifts.len()))
+                    .size(self.layout.value_size));
+                for drift in drifts {
+                    col = col.push(Text::new(to_breakable(drift.describe())).size(self.layout.value_size));
+                }
+                col = col.push(Button::new(&mut self.repair_button, Text::new("Repair")
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::Fill)
+                    .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::RepairRegistry)));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Check failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+
+        col = col.push(Button::new(&mut self.export_button, Text::new("Export .reg file")
+                    .horizontal_alignment(HorizontalAlignment::Center))
+                .width(Length::Fill)
+                .on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::ExportRegistration)));
+
+        match &self.export_result {
+            Some(Ok(path)) => {
+                col = col.push(Text::new(to_breakable(format!("Exported to {}", path.to_str().unwrap())))
+                    .size(self.layout.value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Export failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+
+        let mut restore_button = Button::new(&mut self.restore_button, Text::new("Restore previous registry values")
+                .horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::Fill);
+        if has_backup {
+            restore_button = restore_button.on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::RestoreRegistry));
+        }
+        col = col.push(restore_button);
+
+        match &self.restore_result {
+            Some(Ok(())) => {
+                col = col.push(Text::new("Registry values restored").size(self.layout.value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Restore failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ProfileView {
+    installation_id: InstallationId,
+    layout: Layout,
+    name: String,
+    /// Why a profile couldn't be added or switched to, or that the profiles' userdata has been moved
+    message: Option<String>,
+    profile_buttons: Vec<button::State>,
+    name_input: text_input::State,
+    add_button: button::State
+}
+
+impl ProfileView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            name: String::new(),
+            message: None,
+            profile_buttons: vec![],
+            name_input: text_input::State::default(),
+            add_button: button::State::default()
+        }
+    }
+
+    fn view(&mut self, installation: &Installation) -> Element<Message> {
+        let installation_id = self.installation_id;
+        let profiles = std::iter::once(None)
+            .chain(installation.profiles.iter().cloned().map(Some))
+            .collect::<Vec<Option<String>>>();
+        self.profile_buttons.resize(profiles.len(), button::State::default());
+
+        let mut row = Row::new().spacing(10);
+        for (profile, state) in profiles.into_iter().zip(self.profile_buttons.iter_mut()) {
+            let is_current = profile == installation.profile;
+            let name = profile.clone().unwrap_or(String::from("Default"));
+            let mut button = Button::new(state, Text::new(match is_current {
+                    true => format!("{} (current)", name),
+                    false => name
+                }).size(self.layout.value_size));
+            if !is_current && !installation.in_progress {
+                button = button.on_press(Message::SelectProfile(installation_id, profile));
+            }
+            row = row.push(button);
+        }
+
+        let mut add_button = Button::new(&mut self.add_button, Text::new("Add profile")
+                .horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::FillPortion(1));
+        if !self.name.trim().is_empty() && !installation.in_progress {
+            add_button = add_button.on_press(Message::InstallationEvent(installation_id, InstallationEvent::AddProfile));
+        }
+
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Userdata profiles").size(self.layout.title_size))
+            .push(row)
+            .push(Row::new().spacing(10)
+                .push(Container::new(TextInput::new(&mut self.name_input, "Profile name", &self.name,
+                                                    move |name| Message::InstallationEvent(installation_id, InstallationEvent::ProfileNameChanged(name)))
+                        .size(self.layout.value_size))
+                    .height(Length::Units(self.layout.text_height))
+                    .width(Length::FillPortion(3))
+                    .center_y())
+                .push(add_button));
+
+        if let Some(msg) = &self.message {
+            col = col.push(Text::new(to_breakable(msg.clone())).size(self.layout.value_size));
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OptionsView {
+    installation_id: InstallationId,
+    layout: Layout,
+    /// Key, (edited) value and input of the settings, loaded on request
+    settings: Vec<(&'static str, String, text_input::State)>,
+    result: Option<Result<String, String>>,
+    load_button: button::State,
+    save_button: button::State
+}
+
+impl OptionsView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            settings: vec![],
+            result: None,
+            load_button: button::State::default(),
+            save_button: button::State::default()
+        }
+    }
+
+    /// Reads the current values from the Options.ini of the installation's current profile
+    fn load(&mut self, installation: &Installation) {
+        match installation.read_options() {
+            Ok(options) => {
+                self.settings = options::editable_keys().into_iter()
+                    .map(|key| (key, options.get(key).unwrap_or("").to_string(), text_input::State::default()))
+                    .collect();
+                self.result = None;
+            },
+            Err(msg) => self.result = Some(Err(msg))
+        }
+    }
+
+    /// Writes the settings which have a value, keeping the rest of the file as it is
+    fn save(&mut self, installation: &Installation) {
+        let settings = &self.settings;
+        self.result = Some(installation.read_options()
+            .and_then(|mut options| {
+                for (key, value, _) in settings.iter().filter(|(_, value, _)| !value.trim().is_empty()) {
+                    options.set_parsed(key, value)?;
+                }
+                installation.save_options(&options)
+            })
+            .map(|_| String::from("Options.ini saved")));
+    }
+
+    fn view(&mut self) -> Element<Message> {
+        let installation_id = self.installation_id;
+        let mut save_button = Button::new(&mut self.save_button, Text::new("Save")
+                .horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::FillPortion(1));
+        if !self.settings.is_empty() {
+            save_button = save_button.on_press(Message::InstallationEvent(installation_id, InstallationEvent::SaveOptions));
+        }
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Game options")
+                .size(self.layout.title_size)
+                .width(Length::Fill)
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .height(Length::Units(self.layout.text_height)))
+            .push(Row::new().spacing(10)
+                .push(Button::new(&mut self.load_button, Text::new(match self.settings.is_empty() {
+                        true => "Load Options.ini",
+                        false => "Reload"
+                    }).horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::FillPortion(1))
+                    .on_press(Message::InstallationEvent(installation_id, InstallationEvent::LoadOptions)))
+                .push(save_button));
+
+        for (key, value, state) in self.settings.iter_mut() {
+            let key = *key;
+            col = col.push(Row::new().spacing(10)
+                .push(Text::new(key)
+                    .size(self.layout.value_size)
+                    .width(Length::FillPortion(1)))
+                .push(TextInput::new(state, "not set", value,
+                                     move |value| Message::InstallationEvent(installation_id, InstallationEvent::OptionChanged(key, value)))
+                    .size(self.layout.value_size)
+                    .width(Length::FillPortion(2))));
+        }
+
+        match &self.result {
+            Some(Ok(msg)) => {
+                col = col.push(Text::new(msg.clone()).size(self.layout.value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Failed: {}", msg)).size(self.layout.value_size));
+            },
+            None => {}
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RelocateView {
+    installation_id: InstallationId,
+    layout: Layout,
+    target: String,
+    error: Option<String>,
+    target_input: text_input::State,
+    relocate_button: button::State
+}
+
+impl RelocateView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            target: String::new(),
+            error: None,
+            target_input: text_input::State::default(),
+            relocate_button: button::State::default()
+        }
+    }
+
+    fn view(&mut self) -> Element<Message> {
+        let installation_id = self.installation_id;
+        let mut relocate_button = Button::new(&mut self.relocate_button, Text::new("Move")
+                .horizontal_alignment(HorizontalAlignment::Center))
+            .width(Length::FillPortion(1));
+        if !self.target.trim().is_empty() {
+            relocate_button = relocate_button.on_press(Message::InstallationEvent(self.installation_id, InstallationEvent::Relocate));
+        }
+        let mut col = Column::new().spacing(10)
+            .push(Row::new().spacing(10)
+                .push(Container::new(TextInput::new(&mut self.target_input, "Move to (e.g. D:\\Games\\BFME2)", &self.target,
+                                                    move |target| Message::InstallationEvent(installation_id, InstallationEvent::RelocationTargetChanged(target)))
+                        .size(self.layout.value_size))
+                    .height(Length::Units(self.layout.text_height))
+                    .width(Length::FillPortion(3))
+                    .center_y())
+                .push(relocate_button));
+
+        if let Some(msg) = &self.error {
+            col = col.push(Text::new(format!("Can't move the installation: {}", msg)).size(self.layout.value_size));
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct UninstallView {
+    installation_id: InstallationId,
+    layout: Layout,
+    remove_userdata: bool,
+    /// The uninstall button has been pressed and waits for confirmation
+    confirming: bool,
+    error: Option<String>,
+    uninstall_button: button::State,
+    cancel_button: button::State
+}
+
+impl UninstallView {
+
+    fn new(installation_id: InstallationId, layout: Layout) -> Self {
+        Self {
+            installation_id,
+            layout,
+            remove_userdata: false,
+            confirming: false,
+            error: None,
+            uninstall_button: button::State::default(),
+            cancel_button: button::State::default()
+        }
+    }
+
+    fn view(&mut self, installation: &Installation) -> Element<Message> {
+        let installation_id = self.installation_id;
+        let mut col = Column::new().spacing(10)
+            .push(Checkbox::new(self.remove_userdata, "Also delete userdata (profiles, heroes, options)",
+                                move |checked| Message::InstallationEvent(installation_id, InstallationEvent::RemoveUserdataToggled(checked)))
+                .text_size(self.layout.value_size));
+
+        col = match self.confirming {
+            true => col
+                .push(Text::new(to_breakable(format!("Delete {}{}? This can't be undone.", installation.path, match self.remove_userdata {
+                        true => " and its userdata",
+                        false => ""
+                    })))
+                    .size(self.layout.value_size))
+                .push(Row::new().spacing(10)
+                    .push(Button::new(&mut self.uninstall_button, Text::new("Uninstall")
+                            .horizontal_alignment(HorizontalAlignment::Center))
+                        .width(Length::FillPortion(1))
+                        .on_press(Message::InstallationEvent(installation_id, InstallationEvent::ConfirmUninstall)))
+                    .push(Button::new(&mut self.cancel_button, Text::new("Cancel")
+                            .horizontal_alignment(HorizontalAlignment::Center))
+                        .width(Length::FillPortion(1))
+                        .on_press(Message::InstallationEvent(installation_id, InstallationEvent::CancelUninstall)))),
+            false => {
+                let mut uninstall_button = Button::new(&mut self.uninstall_button, Text::new("Uninstall")
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::Fill);
+                if !installation.in_progress {
+                    uninstall_button = uninstall_button.on_press(Message::InstallationEvent(installation_id, InstallationEvent::Uninstall));
+                }
+                col.push(uninstall_button)
+            }
+        };
+
+        if let Some(msg) = &self.error {
+            col = col.push(Text::new(format!("Uninstall failed: {}", msg)).size(self.layout.value_size));
+        }
+        col.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MapsView {
+    layout: Layout,
+    /// One index per game: its installations are only compared with each other and the shared folder
+    pub indexes: Vec<(Game, MapIndex)>,
+    /// Number of the latest refresh, whose index is waited for while `indexing`
+    pub generation: usize,
+    pub indexing: bool,
+    pub result: Option<Result<String, String>>,
+    shared_dir_input: text_input::State,
+    refresh_button: button::State,
+    copy_buttons: Vec<Vec<button::State>>
+}
+
+impl MapsView {
+
+    pub fn new() -> Self {
+        Self {
+            layout: Layout {
+                header_size: 24,
+                title_size: 20,
+                value_size: 16,
+                text_height: 36
+            },
+            indexes: vec![],
+            generation: 0,
+            indexing: false,
+            result: None,
+            shared_dir_input: text_input::State::default(),
+            refresh_button: button::State::default(),
+            copy_buttons: vec![]
+        }
+    }
+
+    /// The indexed location; the shared folder is the same in all indexes
+    pub fn location(&self, location: MapLocation) -> Option<&IndexedLocation> {
+        self.indexes.iter().find_map(|(_, index)| index.location(location))
+    }
+
+    pub fn view(&mut self, shared_dir: &str) -> Element<Message> {
+        let value_size = self.layout.value_size;
+        let title_size = self.layout.title_size;
+        self.copy_buttons.resize(self.indexes.len(), vec![]);
+
+        let mut col = Column::new().spacing(10)
+            .push(Text::new("Maps").size(self.layout.header_size))
+            .push(Row::new().spacing(10)
+                .push(Container::new(TextInput::new(&mut self.shared_dir_input, "Shared map folder (e.g. D:\\Maps)", shared_dir,
+                                                    Message::SharedMapsDirChanged)
+                        .size(value_size))
+                    .height(Length::Units(self.layout.text_height))
+                    .width(Length::FillPortion(3))
+                    .center_y())
+                .push(Button::new(&mut self.refresh_button, Text::new("Refresh")
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::FillPortion(1))
+                    .on_press(Message::RefreshMaps)));
+
+        if self.indexing {
+            col = col.push(Text::new("Indexing maps...").size(value_size));
+        }
+        match &self.result {
+            Some(Ok(msg)) => {
+                col = col.push(Text::new(to_breakable(msg.clone())).size(value_size));
+            },
+            Some(Err(msg)) => {
+                col = col.push(Text::new(format!("Failed: {}", msg)).size(value_size));
+            },
+            None => {}
+        }
+        for error in self.indexes.iter().flat_map(|(_, index)| index.errors.iter()) {
+            col = col.push(Text::new(to_breakable(error.clone())).size(value_size));
+        }
+        if !self.indexing && self.indexes.iter().all(|(_, index)| index.maps.is_empty()) {
+            return col.push(Text::new("No maps found").size(value_size)).into()
+        }
+
+        for ((game, index), buttons) in self.indexes.iter().zip(self.copy_buttons.iter_mut()) {
+            if index.maps.is_empty() {
+                continue;
+            }
+            let locations = &index.locations;
+            buttons.resize(index.maps.len() * locations.len(), button::State::default());
+
+            let mut header = Row::new().spacing(10)
+                .push(Text::new(game.to_string()).size(title_size).width(Length::FillPortion(3)));
+            for location in locations {
+                header = header.push(Text::new(location.label.clone()).size(title_size).width(Length::FillPortion(1)));
+            }
+            col = col.push(Space::with_height(Length::Units(10)))
+                .push(header);
+
+            // Each map is compared with its reference version, which is also where missing or different copies are taken from
+            for ((map, hashes), states) in index.maps.iter().zip(buttons.chunks_mut(locations.len().max(1))) {
+                let reference = index.reference(map);
+                let mut row = Row::new().spacing(10)
+                    .push(Text::new(to_breakable(map.clone())).size(value_size).width(Length::FillPortion(3)));
+                for ((location, hash), state) in locations.iter().zip(hashes.iter()).zip(states.iter_mut()) {
+                    let cell: Element<Message> = match (hash, &reference) {
+                        (Some(hash), Some((_, reference_hash))) if hash == reference_hash => {
+                            Text::new(&hash[..8]).size(value_size).into()
+                        },
+                        (hash, Some((i, _))) => {
+                            let label = match hash {
+                                Some(_) => "Replace",
+                                None => "Copy here"
+                            };
+                            Column::new()
+                                .push(Text::new(hash.as_ref().map(|hash| format!("{} (differs)", &hash[..8])).unwrap_or(String::from("missing")))
+                                    .size(value_size))
+                                .push(Button::new(state, Text::new(label).size(value_size))
+                                    .on_press(Message::CopyMap(map.clone(), locations[*i].location, location.location)))
+                                .into()
+                        },
+                        (_, None) => Text::new("missing").size(value_size).into()
+                    };
+                    row = row.push(Container::new(cell).width(Length::FillPortion(1)));
+                }
+                col = col.push(row);
+            }
+        }
+        col.into()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum InstallationEvent {
     AttributeUpdate(InstallationAttribute, String),
     AttributeClicked(InstallationAttribute),
     InstallerEvent(InstallerEvent),
+    ScanUserdata,
+    UserdataScanned(Result<Vec<ScannedFile>, String>),
+    ExportRegistration,
+    Activate,
+    Activated(Result<RegistrationReport, String>),
+    CheckRegistry,
+    RepairRegistry,
+    RegistryChecked(Result<Vec<Drift>, String>),
+    RestoreRegistry,
+    QuarantineTainted,
+    RestoreQuarantined,
+    /// The new quarantine directory, None after restoring (see userdata::quarantine)
+    QuarantineDone(Result<Option<PathBuf>, String>),
+    ExportReport,
+    /// Differences to the other installation (see Message::CompareInstallations)
+    ComparisonDone(InstallationId, Result<Vec<String>, String>),
+    BackupUserdata,
+    RestoreUserdata(PathBuf),
+    UserdataBackupDone(Result<String, String>),
+    ProfileNameChanged(String),
+    AddProfile,
+    LoadOptions,
+    OptionChanged(&'static str, String),
+    SaveOptions,
+    RelocationTargetChanged(String),
+    Relocate,
+    RemoveUserdataToggled(bool),
+    Uninstall,
+    ConfirmUninstall,
+    CancelUninstall,
     StartInstallation,
     Stub
 }
\ No newline at end of file
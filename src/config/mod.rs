@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize, Deserializer};
+use crate::common::{Game, Installation, InstallationAttribute, Language, app_config_dir, app_data_dir};
+use crate::library::Library;
+use crate::reg::backend::registry;
+use crate::reg::elevation::RegistrationMode;
+
+/// Defaults for new installations, updated with the user's last choices
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub language: Language,
+    pub registration_mode: RegistrationMode,
+    pub resolution: String
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            language: Language::default(),
+            registration_mode: RegistrationMode::default(),
+            resolution: String::from("1024x768")
+        }
+    }
+}
+
+/// Everything the manager persists. Stored as config.json in the platform's config directory
+/// (e.g. %APPDATA%\BFME2 Installer or ~/.config/BFME2 Installer).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub library: Library,
+    /// Install path for new installations per game (as seen by the game, e.g. C:\Program Files (x86)\...)
+    #[serde(deserialize_with = "known_games")]
+    pub default_install_paths: HashMap<Game, String>,
+    /// Directories containing game archives which were used as install source, most recent first
+    pub sources: Vec<String>,
+    pub preferences: Preferences,
+    /// Folder with maps to copy into installations, laid out like the game's Maps folder
+    pub shared_maps_dir: Option<String>,
+    /// Set if config.json couldn't be parsed nor moved aside, so saving would overwrite it
+    #[serde(skip)]
+    read_only: bool
+}
+
+/// Drops the entries of games without a definition instead of failing
+fn known_games<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Game, String>, D::Error> {
+    Ok(HashMap::<String, String>::deserialize(deserializer)?.into_iter()
+        .filter_map(|(id, value)| match Game::from_id(&id) {
+            Some(game) => Some((game, value)),
+            None => {
+                println!("WARNING: Ignoring the default install path of unknown game {}", id);
+                None
+            }
+        })
+        .collect())
+}
+
+impl Config {
+
+    pub fn path() -> PathBuf {
+        app_config_dir().join("config.json")
+    }
+
+    pub fn load() -> Config {
+        let path = Self::path();
+        let mut config = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<Config>(&contents).unwrap_or_else(|e| {
+                println!("ERROR: Could not parse {:?}: {}", path, e);
+                // Keep the file for the user to fix instead of overwriting it with the defaults
+                let backup_path = path.with_extension("json.bak");
+                match fs::rename(&path, &backup_path) {
+                    Ok(()) => {
+                        println!("Moved it to {:?}, starting with the default configuration", backup_path);
+                        Config::default()
+                    },
+                    Err(e) => {
+                        println!("ERROR: Could not move it to {:?}: {}. The configuration won't be saved.", backup_path, e);
+                        Config { read_only: true, ..Config::default() }
+                    }
+                }
+            }),
+            Err(_) => Self::migrate()
+        };
+        config.library.refresh_active();
+        config
+    }
+
+    /// Takes over the installation library from its previous location (before the config file existed)
+    fn migrate() -> Config {
+        let library_path = app_data_dir().join("library.json");
+        let library = fs::read_to_string(&library_path).ok()
+            .and_then(|contents| serde_json::from_str::<Library>(&contents).ok());
+        match library {
+            Some(library) => {
+                println!("Migrating installation library from {:?}", library_path);
+                Config { library, ..Config::default() }
+            },
+            None => Config::default()
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path();
+        if self.read_only {
+            return Err(format!("Not saving the configuration, {:?} couldn't be read", path))
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+        File::create(&path)
+            .and_then(|mut f| f.write_all(contents.as_bytes()))
+            .map_err(|e| format!("Could not write {:?}: {}", path, e))
+    }
+
+    pub fn default_install_path(&self, game: &Game) -> String {
+        let path = self.default_install_paths.get(game).cloned()
+            .unwrap_or(format!("C:\\Program Files (x86)\\Electronic Arts\\{}", game));
+        registry().from_registry_path(&path).to_str().unwrap().to_owned()
+    }
+
+    /// The most recently used install source which still exists, or else the current directory
+    pub fn default_source(&self) -> String {
+        self.sources.iter()
+            .find(|source| Path::new(source).is_dir())
+            .cloned()
+            .unwrap_or(env::current_dir()
+                .expect("Could not retrieve current directory!")
+                .to_str()
+                .unwrap()
+                .replace("\\\\?\\", ""))
+    }
+
+    pub fn add_source(&mut self, source: &str) {
+        self.sources.retain(|s| s != source);
+        self.sources.insert(0, source.to_string());
+    }
+
+    /// A new (not yet installed) installation with the configured defaults
+    pub fn new_installation(&self, game: Game) -> Installation {
+        let mut installation = Installation::defaults(game);
+        installation.path = self.default_install_path(&game);
+        installation.install_source = Some(self.default_source());
+        installation.language = self.preferences.language;
+        installation.registration_mode = self.preferences.registration_mode;
+        installation.set_attribute(&InstallationAttribute::Resolution, self.preferences.resolution.clone())
+            .expect("Error while setting default resolution");
+        installation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_config_is_kept() {
+        let path = Config::path();
+        create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ \"library\": [").unwrap();
+
+        let config = Config::load();
+        assert!(config.library.installations().is_empty());
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(path.with_extension("json.bak")).unwrap(), "{ \"library\": [");
+
+        config.save().unwrap();
+        let config = Config::load();
+        assert!(config.library.installations().is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn unknown_games_are_ignored() {
+        let config: Config = serde_json::from_str(
+            "{ \"default_install_paths\": { \"BFME1\": \"C:\\\\BFME1\", \"BFME2\": \"C:\\\\BFME2\" } }").unwrap();
+        assert_eq!(config.default_install_paths.len(), 1);
+        assert_eq!(config.default_install_paths[&Game::from_id("BFME2").unwrap()], "C:\\BFME2");
+    }
+}
+
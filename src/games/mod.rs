@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{read_dir, read_to_string};
+use std::io;
+use std::path::PathBuf;
+use once_cell::sync::Lazy;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use crate::common::app_config_dir;
+use crate::reg::{Hive, get_reg_value};
+use crate::reg::backend::FromRegData;
+
+static BFME2_DEFINITION: &str = include_str!("bfme2.json");
+static ROTWK_DEFINITION: &str = include_str!("rotwk.json");
+
+/// Everything the installer needs to know about a game besides its registry definitions
+#[derive(Debug, Clone, Deserialize)]
+pub struct GameDefinition {
+    /// Short name, e.g. BFME2. Used in the installer's own registry keys and file names.
+    pub id: String,
+    pub title: String,
+    pub executable: String,
+    /// Game files are extracted from <archive_prefix>_0.tar.gz, <archive_prefix>_1.tar.gz, ...
+    pub archive_prefix: String,
+    /// Userdata is extracted from userdata.<userdata_prefix>.tar.gz to <userdata_prefix>_<checksum>
+    pub userdata_prefix: String,
+    /// Userdata directory of the retail installer (below %APPDATA%), taken over when adopting an installation
+    #[serde(default)]
+    pub retail_userdata_dir: Option<String>,
+    /// Id of the game this one expands. Checksums are combined with the base game's installation.
+    #[serde(default)]
+    pub base_game: Option<String>,
+    /// Registry definitions (.reg template) of the game: one bundled with the installer (e.g. bfme2.reg) or a file in
+    /// the user's games directory. Without one, all registry definitions come from the user's override file.
+    #[serde(default)]
+    pub registry_template: Option<String>,
+    /// Where the registry definitions put the values a registered installation is loaded from
+    pub registry: RegistryLayout
+}
+
+/// Registry values of a registered installation. They have to be written by the game's registry definitions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistryLayout {
+    /// The installer's own key, with the values `checksum`, `sync_fingerprint` and `install_path`
+    pub installer_key: RegistryValue,
+    /// The install path as written for the game, used for registrations without the installer's own copy
+    pub install_path: RegistryValue,
+    pub userdata_leaf_name: RegistryValue,
+    pub ergc: RegistryValue,
+    /// Locale of the game's language, if the game has one
+    #[serde(default)]
+    pub locale: Option<RegistryValue>
+}
+
+/// A value (or key, for `installer_key`) in the registry
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistryValue {
+    /// HKLM or HKCU
+    #[serde(default = "default_hive")]
+    pub hive: String,
+    pub key: String,
+    /// Empty for the key's default value
+    #[serde(default)]
+    pub name: String
+}
+
+fn default_hive() -> String {
+    String::from("HKLM")
+}
+
+impl RegistryValue {
+    pub fn read<T: FromRegData>(&self) -> io::Result<T> {
+        let hive = Hive::from_name(&self.hive).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        get_reg_value(hive, &self.key, &self.name)
+    }
+
+    /// A value in this key (e.g. of the installer's own key)
+    pub fn value(&self, name: &str) -> RegistryValue {
+        RegistryValue { hive: self.hive.clone(), key: self.key.clone(), name: name.to_string() }
+    }
+}
+
+/// The bundled definitions plus any *.json files in the user's games directory. A user definition
+/// with the id of a bundled one replaces it.
+static DEFINITIONS: Lazy<Vec<GameDefinition>> = Lazy::new(|| {
+    let mut definitions: Vec<GameDefinition> = vec![BFME2_DEFINITION, ROTWK_DEFINITION].iter()
+        .map(|contents| serde_json::from_str(contents).expect("Invalid bundled game definition"))
+        .collect();
+    let mut paths = read_dir(definitions_dir())
+        .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect::<Vec<PathBuf>>())
+        .unwrap_or_default();
+    paths.sort();
+    for path in paths {
+        let definition = read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str::<GameDefinition>(&contents).map_err(|e| e.to_string()));
+        match definition {
+            Ok(definition) => {
+                println!("Loaded game definition {} from {:?}", definition.id, path);
+                definitions.retain(|d| d.id != definition.id);
+                definitions.push(definition);
+            },
+            Err(e) => println!("ERROR: Invalid game definition {:?}: {}", path, e)
+        }
+    }
+    definitions
+});
+
+/// Location of the user's game definitions
+pub fn definitions_dir() -> PathBuf {
+    app_config_dir().join("games")
+}
+
+/// A game with a definition, identified by the definition's id
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Game(&'static str);
+
+impl Game {
+    pub fn all() -> Vec<Game> {
+        DEFINITIONS.iter().map(|d| Game(d.id.as_str())).collect()
+    }
+
+    pub fn from_id(id: &str) -> Option<Game> {
+        DEFINITIONS.iter().find(|d| d.id == id).map(|d| Game(d.id.as_str()))
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.0
+    }
+
+    pub fn definition(&self) -> &'static GameDefinition {
+        DEFINITIONS.iter().find(|d| d.id == self.0)
+            .expect("Game without definition")
+    }
+
+    pub fn base_game(&self) -> Option<Game> {
+        self.definition().base_game.as_ref().and_then(|id| Game::from_id(id))
+    }
+
+    /// Name of the userdata directory for an installation with the given checksum
+    pub fn userdata_leaf_name(&self, checksum: &str) -> String {
+        format!("{}_{}", self.definition().userdata_prefix, checksum)
+    }
+}
+
+impl Display for Game {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Game {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Game::from_id(&id).ok_or_else(|| serde::de::Error::custom(format!("Unknown game {}", id)))
+    }
+}
@@ -1,24 +1,24 @@
 use iced::{Column, Text, Element, Button, button, TextInput, text_input, Subscription, ProgressBar, progress_bar, Background, Color, Command};
-use super::common::{Message, Game, Installation, InstallationAttribute, format_ergc};
+use super::common::{Message, Game, Installation, InstallationAttribute, format_ergc, to_breakable};
 use super::reg;
+use super::userdata;
+use super::relocate::Relocation;
 use super::components::{InstallationEvent};
-use super::checksums::{write_checksums_file};
+use super::checksums::{write_checksums_file, write_big_checksums_file};
 use std::convert::identity;
-use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::fs::{File, OpenOptions, create_dir_all, remove_dir_all};
-use std::io::{Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{OpenOptions, create_dir_all, remove_dir_all};
 use std::path::{Path, PathBuf};
 use iced::progress_bar::Style;
-use crate::checksums::{generate_files_list, ChecksumGenerator, calculate_hash};
+use crate::checksums::{generate_files_list, ChecksumGenerator, calculate_hash, calculate_sync_fingerprint, read_checksums_file, read_big_checksums_file};
+use crate::checksums::report::ValidationReport;
 use crate::common::InstallationProgress;
-use handlebars::{Handlebars, RenderError};
-use winreg::{RegValue, RegKey};
-use winreg::enums::*;
+use crate::reg::Hive;
+use crate::reg::elevation::{RegistrationReport, affected_entries, write_value, write_pending_reg_file};
 
 #[derive(Debug, Clone)]
 pub enum InstallerEvent {
     Next,
-    RegistrationDone,
     // ExtractionProgressed((usize, ExtractionProgress)),
     // ChecksumGenerationProgressed((usize, ChecksumProgress)),
 }
@@ -31,6 +31,8 @@ pub enum InstallerStep {
     Install,
     Validate,
     UserData,
+    MigrateUserData,
+    Relocate,
     Done,
     Error
 }
@@ -58,6 +60,25 @@ impl InstallerStep {
     pub fn validation_steps() -> Vec<InstallerStep> {
         vec![InstallerStep::Inactive, InstallerStep::Validate, InstallerStep::Register, InstallerStep::Done]
     }
+
+    /// Moves the installation and validates it at the new path. The registry only needs to be updated
+    /// if the installation is the one the game currently uses.
+    pub fn relocation_steps(register: bool) -> Vec<InstallerStep> {
+        let mut steps = vec![InstallerStep::Inactive, InstallerStep::Relocate, InstallerStep::Validate];
+        if register {
+            steps.push(InstallerStep::Register);
+        }
+        steps.push(InstallerStep::Done);
+        steps
+    }
+
+    /// Takes over an existing (e.g. retail) installation at the installation's path
+    pub fn adoption_steps() -> Vec<InstallerStep> {
+        vec![
+            InstallerStep::Inactive, InstallerStep::Validate,
+            InstallerStep::MigrateUserData, InstallerStep::Register, InstallerStep::Done
+        ]
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -72,7 +93,14 @@ pub struct Installer {
     progress: f32,
     progress_message: String,
     steps: Vec<InstallerStep>,
-    processing_state: ProcessingState
+    processing_state: ProcessingState,
+    registration_report: Option<RegistrationReport>,
+    /// Where a relocation moves the installation to
+    relocation_target: Option<String>,
+    /// The old directory of an installation which was copied (rather than renamed) to the relocation target
+    relocation_source: Option<String>,
+    /// Checksum the validation has to confirm (after a relocation)
+    expected_checksum: Option<String>
 }
 
 struct RegRenderData {
@@ -91,6 +119,7 @@ enum ProcessingState {
     Validation(String, Vec<String>, Vec<InstallationProgress>, String),
     Installation(Game, String, String, f32, String),
     UserDataInstallation(Game, String, f32, String),
+    Relocation(String, String, f32, String),
     Failure(String),
     Idle
 }
@@ -123,40 +152,48 @@ impl Installer {
             progress: 0.0,
             progress_message: String::from("NONE"),
             steps,
-            processing_state: ProcessingState::Idle
+            processing_state: ProcessingState::Idle,
+            registration_report: None,
+            relocation_target: None,
+            relocation_source: None,
+            expected_checksum: None
         };
         installer
 
     }
 
+    pub fn relocation(installation: &Installation, target: String, register: bool) -> Installer {
+        let mut installer = Self::new(InstallerStep::relocation_steps(register));
+        installer.relocation_target = Some(target);
+        installer.expected_checksum = Some(installation.checksum.clone());
+        installer
+    }
+
+    /// Whether the installer registers the installation (making it the active one)
+    pub fn registers(&self) -> bool {
+        self.steps.contains(&InstallerStep::Register)
+    }
+
+    /// The old directory of a copied installation, which is still there until the validation at the new path
+    /// has finished. It is removed if the checksum matches, or else used again.
+    pub fn take_relocation_source(&mut self) -> Option<String> {
+        self.relocation_source.take()
+    }
+
+    pub fn verify_checksum(&self, checksum: &str) -> Result<(), String> {
+        match &self.expected_checksum {
+            Some(expected) if expected != checksum => Err(format!(
+                "The checksum changed from {} to {} while moving the installation. Reinstall it or check the validation report.",
+                expected, checksum)),
+            _ => Ok(())
+        }
+    }
+
     pub fn update(&mut self, installation: &Installation, event: InstallerEvent) -> Command<Message> {
-        //let game = self.data.game;
         match event {
-            InstallerEvent::Next => {
-                self.proceed(installation);
-                match self.current_step {
-                    InstallerStep::Register => {
-                        let install_path = String::from(&installation.path);
-                        let ergc = String::from(&installation.ergc);
-                        let checksum = String::from(&installation.checksum);
-                        let game = installation.game;
-
-                        let future = async move {
-                            if let Err(msg) = Self::register(&install_path, &ergc, &checksum, &game) {
-                                println!("ERROR: {}", msg);
-                            }
-                            game
-                        };
-                        Command::perform(future, |g| Message::InstallationEvent(g, InstallationEvent::InstallerEvent(InstallerEvent::RegistrationDone)))
-                    },
-                    _ => Command::none()
-                }
-            },
-            InstallerEvent::RegistrationDone => {
-                self.proceed(installation);
-                Command::none()
-            },
-            _ => Command::none()
+            // The step's work (e.g. registering) is started by proceed, its result arrives as InstallationProgress
+            // (see on_progress), which moves on to the following step or to the error step
+            InstallerEvent::Next => self.proceed(installation)
         }
     }
 
@@ -166,6 +203,8 @@ impl Installer {
             InstallerStep::Install => self.install_view(installation),
             InstallerStep::Validate => self.validate_view(installation),
             InstallerStep::UserData => self.validate_view(installation),
+            InstallerStep::MigrateUserData => self.migration_view(installation),
+            InstallerStep::Relocate => self.relocation_view(installation),
             InstallerStep::Register => self.registration_view(installation),
             InstallerStep::Done => self.completion_view(installation),
             InstallerStep::Error => self.error_view(installation),
@@ -190,18 +229,14 @@ impl Installer {
             InstallerStep::Register => {
                 
 
-                let install_path = String::from(&installation.path);
-                let ergc = String::from(&installation.ergc);
-                let checksum = String::from(&installation.checksum);
-                let game = installation.game;
-
+                let installation = installation.clone();
                 let future = async move {
-                    if let Err(msg) = Self::register(&install_path, &ergc, &checksum, &game) {
-                        println!("ERROR: {}", msg);
+                    match Self::register(&installation) {
+                        Ok(report) => InstallationProgress::Registered(report),
+                        Err(msg) => InstallationProgress::Errored(format!("Registration failed: {}", msg))
                     }
-                    game
                 };
-                Command::perform(future, |g| Message::Progressed((0, InstallationProgress::Finished)))
+                Command::perform(future, |progress| Message::Progressed((0, progress)))
             },
             InstallerStep::Download => todo!(),
             InstallerStep::Install => {
@@ -218,18 +253,41 @@ impl Installer {
             InstallerStep::UserData => {
                 let install_source = installation.install_source.as_ref().ok_or(()).expect("Error: installation source not set!").clone();
                 let game = installation.game;
+                // The userdata archive overwrites heroes, profiles and options of an earlier installation
+                match userdata::backup::create_if_present(installation) {
+                    Ok(Some(path)) => println!("Backed up the existing userdata to {:?}", path),
+                    Ok(None) => {},
+                    Err(msg) => println!("ERROR: Could not back up the existing userdata: {}", msg)
+                }
                 // let userdata_path = installation.get_userdata_path()
                 //     .expect("Could not retrieve userdata path!");
                 self.processing_state = ProcessingState::UserDataInstallation(game, install_source, 0.0, String::from(""));
                 Command::none()
             },
-            InstallerStep::Done => {
-                let game = installation.game.clone();
+            InstallerStep::Relocate => {
+                let target = self.relocation_target.clone().expect("Error: Relocation target needs to be set!");
+                self.processing_state = ProcessingState::Relocation(installation.path.clone(), target, 0.0, String::from(""));
+                Command::none()
+            },
+            InstallerStep::MigrateUserData => {
+                let installation = installation.clone();
+                let id = installation.id;
+                // The retail Options.ini is kept as it is, its resolution is taken over by the installation
+                // (see Message::UserdataMigrated). Options.ini is only written if it has no resolution.
                 let future = async move {
-                    game
+                    userdata::migrate_retail(&installation)
+                        .and_then(|_| installation.read_options())
+                        .and_then(|options| match options.resolution() {
+                            Some(resolution) => Ok(Some(resolution)),
+                            None => installation.write_options().map(|_| None)
+                        })
+                        .map_err(|msg| format!("Userdata migration failed: {}", msg))
                 };
-                Command::perform(future, |g| Message::InstallationComplete(g))
+                Command::perform(future, move |result| Message::UserdataMigrated(id, result))
             },
+            // The completion view lists the registry values which still need admin rights, its Ok button
+            // completes the installation
+            InstallerStep::Done => Command::none(),
             InstallerStep::Error => todo!(),
         }
     }
@@ -237,16 +295,21 @@ impl Installer {
     pub fn subscriptions(&self, installation: &Installation) -> Vec<iced::Subscription<(usize, InstallationProgress)>> {
         match &self.processing_state {
             ProcessingState::Validation(install_path, files, _, _) => self.validation_task(install_path, files),
-            ProcessingState::Installation(game, install_source, install_path, _, _) => self.installation_task(game.to_string(), install_source.clone(), install_path.clone()),
+            ProcessingState::Installation(game, install_source, install_path, _, _) => self.installation_task(game, install_source.clone(), install_path.clone()),
             ProcessingState::UserDataInstallation(game, install_source, _, _) => 
-                self.userdata_installation_task(game.to_string(), install_source.clone(), installation),
+                self.userdata_installation_task(game, install_source.clone(), installation),
+            ProcessingState::Relocation(from, to, _, _) => vec![iced::Subscription::from_recipe(Relocation {
+                id: 0,
+                from: from.clone(),
+                to: to.clone()
+            })],
             ProcessingState::Idle|ProcessingState::Failure(_) => vec![],
         }
     }
 
-    pub fn installation_task(&self, game_str: String, install_source: String, install_path: String) -> Vec<iced::Subscription<(usize, InstallationProgress)>>  {
+    pub fn installation_task(&self, game: &Game, install_source: String, install_path: String) -> Vec<iced::Subscription<(usize, InstallationProgress)>>  {
         let extraction_queue = (0..30)
-            .map(|n| format!("{}/{}_{}.tar.gz", install_source, game_str, n))
+            .map(|n| format!("{}/{}_{}.tar.gz", install_source, game.definition().archive_prefix, n))
             .filter(|p| Path::new(p).exists())
             .collect::<Vec<String>>();
 
@@ -264,7 +327,7 @@ impl Installer {
         vec![iced::Subscription::from_recipe(extraction)]
     }
 
-    pub fn userdata_installation_task(&self, game_str: String, install_source: String, installation: &Installation) -> Vec<iced::Subscription<(usize, InstallationProgress)>> {
+    pub fn userdata_installation_task(&self, game: &Game, install_source: String, installation: &Installation) -> Vec<iced::Subscription<(usize, InstallationProgress)>> {
         let checksum = &installation.checksum;
         if checksum.is_empty() {
             return vec![];
@@ -273,7 +336,7 @@ impl Installer {
         let userdata_path = installation.get_userdata_path().expect("ERROR: Could not retrieve userdata path");
 
         let extraction_queue = vec![
-            format!("{}/userdata.{}.tar.gz", install_source, game_str.to_lowercase())
+            format!("{}/userdata.{}.tar.gz", install_source, game.definition().userdata_prefix)
         ];
         create_dir_all(&userdata_path);
         let mut extraction = super::extract::Extraction {
@@ -287,44 +350,67 @@ impl Installer {
 
     pub fn on_progress(&mut self, installation: &Installation, progress: InstallationProgress) -> Command<Message> {
         if let InstallationProgress::Errored(msg) = progress {
-            self.processing_state = ProcessingState::Failure(format!("Extraction failed: {}", msg));
+            self.processing_state = ProcessingState::Failure(match self.processing_state {
+                ProcessingState::Installation(..)|ProcessingState::UserDataInstallation(..) => format!("Extraction failed: {}", msg),
+                ProcessingState::Relocation(..) => format!("Moving the installation failed: {}", msg),
+                _ => msg
+            });
+            self.current_step = InstallerStep::Error;
             return Command::none()
         }
 
         match &mut self.processing_state {
             ProcessingState::Validation(install_path, files, ref mut results,  _) => {
                 match progress {
-                    InstallationProgress::ChecksumResult(path, checksum) => {
+                    InstallationProgress::ChecksumResult(ref path, _)|InstallationProgress::BigChecksumResult(ref path, _, _) => {
                         println!("Checksum calculated for {}", path);
-                        results.push(InstallationProgress::ChecksumResult(path, checksum));
+                        results.push(progress);
                         if files.len() == results.len() {
-                            //Command::perform(future, |g| Message::InstallationEvent(g, InstallationEvent::InstallerEvent(InstallerEvent::RegistrationDone)))
                             
                             let validation_results = results.iter().map(|res| match res {
-                                InstallationProgress::ChecksumResult(path, cs) => Ok(Some((path.clone(), cs.clone()))),
+                                InstallationProgress::ChecksumResult(path, cs) => Ok(Some((path.clone(), cs.clone(), None))),
+                                InstallationProgress::BigChecksumResult(path, cs, entries) => Ok(Some((path.clone(), cs.clone(), Some(entries.clone())))),
                                 InstallationProgress::Skipped => Ok(None),
                                 _ => Err(format!("Invalid validation result: {:#?}", res))
-                            }).collect::<Result<Vec<Option<(String, String)>>, String>>()
+                            }).collect::<Result<Vec<Option<(String, String, Option<Vec<(String, String)>>)>>, String>>()
                             .expect("Error while collecting validation results");
 
+                            let id = installation.id;
                             let game = installation.game.clone();
                             let install_path_clone = install_path.clone();
 
                             let future = async move {
                                 let mut results = validation_results.into_iter().filter_map(identity)
-                                .collect::<Vec<(String, String)>>();
-                                results.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
-                                write_checksums_file(&install_path_clone, results)
+                                .collect::<Vec<(String, String, Option<Vec<(String, String)>>)>>();
+                                results.sort_by(|(path1, _, _), (path2, _, _)| path1.cmp(path2));
+                                let sync_fingerprint = calculate_sync_fingerprint(&results)
+                                    .expect("Error: Could not calculate sync fingerprint!");
+                                let big_results = results.iter()
+                                    .filter_map(|(path, _, entries)| entries.clone().map(|e| (path.clone(), e)))
+                                    .collect::<Vec<(String, Vec<(String, String)>)>>();
+                                let file_results = results.into_iter()
+                                    .map(|(path, cs, _)| (path, cs))
+                                    .collect::<Vec<(String, String)>>();
+                                let previous_results = read_checksums_file(&install_path_clone);
+                                let previous_big_results = read_big_checksums_file(&install_path_clone);
+                                write_checksums_file(&install_path_clone, file_results.clone())
                                     .expect(&format!("Error writing {}\\checksums.txt!", &install_path_clone));
-                                (
-                                    game,
-                                    calculate_hash(PathBuf::from(&install_path_clone).join("checksums.txt"))
-                                        .expect(&format!("Error: Could not calculate checksum for {}\\checksums.txt!", &install_path_clone))
-                                )
+                                write_big_checksums_file(&install_path_clone, big_results.clone())
+                                    .expect(&format!("Error writing {}\\big_checksums.txt!", &install_path_clone));
+                                let checksum = calculate_hash(PathBuf::from(&install_path_clone).join("checksums.txt"))
+                                    .expect(&format!("Error: Could not calculate checksum for {}\\checksums.txt!", &install_path_clone));
+
+                                // Exported on request (see InstallationEvent::ExportReport)
+                                let report = ValidationReport::new(game.to_string(), checksum.clone(), sync_fingerprint.clone(),
+                                                                   &install_path_clone, &file_results, &previous_results,
+                                                                   &big_results, &previous_big_results);
+                                (id, checksum, sync_fingerprint, report)
                             };
 
-                            self.proceed(installation);
-                            Command::perform(future, |(g, cs)| Message::AttributeUpdate(g, InstallationAttribute::Checksum, cs))
+                            // Proceeds once the checksum has been stored (see Message::ValidationComplete), as the
+                            // following steps need it
+                            self.processing_state = ProcessingState::Idle;
+                            Command::perform(future, |(id, cs, fp, report)| Message::ValidationComplete(id, cs, fp, report))
                         } else {
                             Command::none()
                         }
@@ -349,33 +435,9 @@ impl Installer {
                 match progress {
                     InstallationProgress::Finished => {
                     
-                        let handlebars = Handlebars::new();
-                        let options_file = PathBuf::from(
-                            installation.get_userdata_path()
-                                .expect("Could not retrieve userdata path!")
-                        ).join("options.ini");
-                        let mut options_tmpl = String::new();
-                        File::open(&options_file)
-                            .expect("Error opening options.ini for reading")
-                            .read_to_string(&mut options_tmpl)
-                            .expect("Error reading options.ini");
-
-                        
-                        let mut data = BTreeMap::new();
-                        let (res_x, res_y) = installation.get_resolution();
-                        data.insert("resolution", format!("{} {}", res_x, res_y));
-                        
-                        File::create(&options_file)
-                            .unwrap()
-                            .write(
-                                handlebars.render_template(&options_tmpl, &data)
-                                .expect("Error generating options.ini!")
-                                .as_bytes()
-                            )
-                            .expect("Error writing options.ini!");
-                        
-
-                        
+                        installation.write_options()
+                            .expect("Error writing Options.ini!");
+
                         self.processing_state = ProcessingState::Idle;
                         self.proceed(installation)
                     },
@@ -386,11 +448,35 @@ impl Installer {
                     _ => Command::none()
                 }
             },
+            ProcessingState::Relocation(from, to, _, _) => {
+                match progress {
+                    InstallationProgress::Relocating(prog, msg) => {
+                        self.processing_state = ProcessingState::Relocation(from.clone(), to.clone(), prog, msg);
+                        Command::none()
+                    },
+                    InstallationProgress::Finished => {
+                        // A rename leaves nothing behind, a copy keeps the old directory
+                        if Path::new(from).exists() {
+                            self.relocation_source = Some(from.clone());
+                        }
+                        // Proceeds once the new path has been stored (see Message::InstallationRelocated)
+                        let id = installation.id;
+                        let target = to.clone();
+                        self.processing_state = ProcessingState::Idle;
+                        Command::perform(async move { (id, target) }, |(id, target)| Message::InstallationRelocated(id, target))
+                    },
+                    _ => Command::none()
+                }
+            },
             ProcessingState::Idle => {
                 match progress {
                     InstallationProgress::Finished => {
                         self.proceed(installation)
                     },
+                    InstallationProgress::Registered(report) => {
+                        self.registration_report = Some(report);
+                        self.proceed(installation)
+                    },
                     InstallationProgress::Errored(msg) => {
                         self.processing_state = ProcessingState::Failure(msg);
                         self.current_step = InstallerStep::Error;
@@ -420,67 +506,123 @@ impl Installer {
         if installation.checksum.is_empty() {  Err(()) } else { Ok(installation.checksum.clone()) }
     }
 
-    fn register(install_path: &str, ergc: &str, checksum: &str, game: &Game) -> Result<(), String> {
-        let canon_path = PathBuf::from(install_path)
-            .canonicalize()
-            .unwrap().to_str()
-            .unwrap()
-            .replace("\\\\?\\", "");
-        let mut reg_data = BTreeMap::new();
-        reg_data.insert("install_path", canon_path.clone());
-        reg_data.insert("install_path_shorthand", canon_path);
-        reg_data.insert("ergc", String::from(ergc));
-        reg_data.insert("checksum", String::from(checksum));
-        let mut handlebars = Handlebars::new();
-
-
-        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-        let reg_entries = match game {
-            Game::BFME2 => reg::BFME2,
-            Game::ROTWK => reg::ROTWK,
+    fn register(installation: &Installation) -> Result<RegistrationReport, String> {
+        let registry = reg::backend::registry();
+        let reg_data = reg::installation_template_data(installation);
+        let game = &installation.game;
+        let mode = installation.registration_mode;
+
+        let reg_entries = reg::regentries(game)?;
+        // The backup is only saved once all values have been written, so a failed registration leaves none behind
+        let previous = match reg::backup::has_backup(game, &installation.checksum) {
+            true => None,
+            false => Some(reg::backup::snapshot(&affected_entries(&reg_entries, mode)?)?)
         };
 
-        if reg_entries.keys.get("HKLM").expect("Unexpected Error!")
-            .entries()
-            .map(|(key, entries)| {
-            entries.entries().map(|(value_name, value)| {
-                match value {
-                    reg::RegValue::Str(val) => {
-                        println!("Write {} to {}", val, key);
-                        match hklm.create_subkey(key) {
-                            Ok((reg_writer, disp)) => reg_writer.set_value(value_name, val),
-                            Err(e) => Err(e)
-                        }
-                    },
-                    reg::RegValue::UInt(val) => {
-                        println!("Write {} to {}", val, key);
-                        match hklm.create_subkey(key) {
-                            Ok((reg_writer, disp)) => reg_writer.set_value(value_name, val),
-                            Err(e) => Err(e)
-                        }
-                    },
-                    reg::RegValue::Template(tmpl) => {
-                        let val = handlebars.render_template(tmpl, &reg_data).unwrap();
-                        println!("Write {} to {}", val, key);
-                        match hklm.create_subkey(key) {
-                            Ok((reg_writer, disp)) => reg_writer.set_value(value_name, &val),
-                            Err(e) => Err(e)
-                        }
-                    },
+        let mut report = RegistrationReport::default();
+        for (hive_name, keys) in reg_entries.keys.iter() {
+            let hive = Hive::from_name(hive_name)?;
+            for (key, entries) in keys.iter() {
+                for (value_name, value) in entries.iter() {
+                    let data = value.render(&reg_data)?;
+                    if let Some(pending) = write_value(registry, hive, key, value_name, &data, mode)? {
+                        report.pending.push(pending);
+                    }
+                }
+            }
+        }
+
+        if let Some(previous) = previous {
+            reg::backup::ensure_backup(game, &installation.checksum, &previous)?;
+        }
+        if !report.is_complete() {
+            println!("{} registry values need admin rights", report.pending.len());
+            report.reg_file = Some(write_pending_reg_file(game, &installation.checksum, &report.pending)?);
+        }
+        Ok(report)
+    }
+
+    /// Re-registers the installation, so the game uses it. If writing fails midway, the previous values are restored.
+    pub fn activate(installation: &Installation) -> Result<RegistrationReport, String> {
+        let reg_entries = affected_entries(&reg::regentries(&installation.game)?, installation.registration_mode)?;
+        let previous = reg::backup::snapshot(&reg_entries)?;
+
+        println!("Activating {} installation {}...", installation.game, installation.checksum);
+        Self::register(installation).or_else(|msg| {
+            println!("ERROR: {}, rolling back...", msg);
+            reg::backup::apply(&previous)
+                .map_err(|e| format!("{} (rollback failed: {})", msg, e))
+                .and(Err(msg))
+        })
+    }
+
+    /// Removes the values of the game's registry entries (including the installer's own checksum key and the
+    /// VirtualStore values written without admin rights) and restores the values the installation replaced when it was first registered (e.g. those of a retail installation).
+    /// Other values in the keys (e.g. written by the retail installer) are kept.
+    fn unregister(installation: &Installation) -> Result<(), String> {
+        let registry = reg::backend::registry();
+        let (game, checksum) = (&installation.game, &installation.checksum);
+        let reg_entries = affected_entries(&reg::regentries(game)?, installation.registration_mode)?;
+
+        for (hive, keys) in reg_entries.keys.iter() {
+            let hive = Hive::from_name(hive)?;
+            for (key, values) in keys.iter() {
+                for name in values.keys() {
+                    println!("Delete {}\\{}", key, name);
+                    match registry.delete_value(hive, key, name) {
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                        result => result.map_err(|e| format!("Could not delete {}\\{}: {}", key, name, e))?
+                    }
                 }
-            }).all(|result: Result<(), std::io::Error>| {
-                if result.is_err() {
-                    println!("ERROR: {}", result.unwrap_err());
-                    return false
+            }
+        }
+
+        // The backup is only removed once it has been applied
+        if reg::backup::has_backup(game, checksum) {
+            reg::backup::restore(game, checksum)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the installation can be uninstalled and returns the directories to delete. Userdata
+    /// directories which another installation in the library uses as well (same checksum) are kept.
+    pub fn uninstall_directories(installation: &Installation, remove_userdata: bool, userdata_in_use: &[String]) -> Result<Vec<PathBuf>, String> {
+        let install_path = PathBuf::from(&installation.path);
+        // Refuse to delete directories which don't look like an installation made by us
+        if install_path.exists() && !install_path.join("checksums.txt").exists() {
+            return Err(format!("{} does not contain checksums.txt, refusing to delete it", installation.path))
+        }
+
+        let mut directories = vec![];
+        if install_path.exists() {
+            directories.push(install_path);
+        }
+        if remove_userdata {
+            for userdata_path in installation.profile_userdata_paths() {
+                if userdata_in_use.iter().any(|path| path.to_lowercase() == userdata_path.to_lowercase()) {
+                    println!("Keeping {}, another installation uses it", userdata_path);
+                } else if Path::new(&userdata_path).exists() {
+                    directories.push(PathBuf::from(userdata_path));
                 }
-                true
-            })
-        }).all(|result| result) {
-            Ok(())
-        } else {
-            Err("An error occurred while setting up the registry".parse().unwrap())
+            }
         }
+        Ok(directories)
+    }
 
+    /// Removes the installation's registry entries (if the game currently uses it) and deletes the given directories
+    /// (see `uninstall_directories`). Deleting the game files takes a while, so this is run as a command.
+    pub fn uninstall(installation: &Installation, is_active: bool, directories: Vec<PathBuf>) -> Result<(), String> {
+        match is_active {
+            true => Self::unregister(installation)?,
+            false => println!("{} installation {} is not active, leaving the registry unchanged", installation.game, installation.path)
+        }
+
+        for directory in directories {
+            println!("Deleting {:?}...", directory);
+            remove_dir_all(&directory)
+                .map_err(|e| format!("Could not delete {}: {}", directory.to_str().unwrap(), e))?;
+        }
+        Ok(())
     }
 
     fn registration_view(&mut self, installation: &Installation) -> Element<Message> {
@@ -494,23 +636,56 @@ impl Installer {
     }
 
 
+    fn relocation_view(&mut self, installation: &Installation) -> Element<Message> {
+        let (progress, message) = match &self.processing_state {
+            ProcessingState::Relocation(_, to, prog, msg) => (*prog, format!("{} ({})", to, msg)),
+            _ => (100.0, String::new())
+        };
+        Self::progress_view(installation, progress, "Moving", to_breakable(message)).into()
+    }
+
+    fn migration_view(&mut self, installation: &Installation) -> Element<Message> {
+        let source = installation.game.definition().retail_userdata_dir.clone().unwrap_or_default();
+        Column::new()
+            .push(Text::new(format!("{:?}", self.current_step)))
+            .push(Text::new(to_breakable(format!("Copying userdata from {}...", source))))
+            .into()
+    }
+
     fn completion_view(&mut self, installation: &Installation) -> Element<Message> {
 
         //Message::InstallerNext(self.current_step.next());
 
-        Column::new()
-            .push(Text::new(format!("{:?}", self.current_step)))
-            .push(Text::new("All done!"))
+        let mut col = Column::new()
+            .push(Text::new(format!("{:?}", self.current_step)));
+        col = match &self.registration_report {
+            Some(report) if !report.is_complete() => {
+                col = col.push(Text::new(format!("{} registry values could not be written without admin rights:",
+                                                 report.pending.len())));
+                for value in &report.pending {
+                    col = col.push(Text::new(to_breakable(format!("{}\\{}\\{}{}", value.hive, value.key, value.name,
+                                                                  if value.virtualized { " (written for the current user only)" } else { "" }))));
+                }
+                match &report.reg_file {
+                    Some(path) => col.push(Text::new(to_breakable(format!(
+                        "Import {} as administrator (double click and confirm) to register the game for all users.",
+                        path.to_str().unwrap())))),
+                    None => col
+                }
+            },
+            _ => col.push(Text::new("All done!"))
+        };
+        col
             .push(Button::new(&mut self.button_states[0],
                                      Text::new("Ok"))
-            .on_press(Message::InstallationComplete(installation.game)))
+            .on_press(Message::InstallationComplete(installation.id)))
             .into()
     }
 
     fn progress_view<'a>(installation: &Installation, progress: f32, title: &'a str, progress_message: String) -> Column<'a, Message> {
         println!("Progress: {}", progress);
         Column::new()
-            .push(Text::new(format!("{:?}", installation.game)).size(20))
+            .push(Text::new(installation.game.definition().title.clone()).size(20))
             .push(Text::new(format!("{}...", title)))
             .push(ProgressBar::new(0.0..=100.0, progress.abs().max(1.0))
                 .style(PbStyle{error: match progress {
@@ -533,7 +708,7 @@ impl Installer {
             view = view.push(Button::new(&mut self.button_states[0],
                                          Text::new("Next"))
                 .on_press(Message::InstallationEvent(
-                    installation.game,
+                    installation.id,
                     InstallationEvent::InstallerEvent(InstallerEvent::Next))));
         }
 
@@ -548,7 +723,7 @@ impl Installer {
 
         Self::progress_view(installation, -100.0, "ERROR", message)
             .push(Button::new(&mut self.button_states[0], Text::new("Okay"))
-                .on_press(Message::InstallationComplete(installation.game)))
+                .on_press(Message::InstallationFailed(installation.id)))
             .into()
     }
 
@@ -615,12 +790,12 @@ impl Installer {
 
     fn default_view<'a>(&'a mut self, installation: &'a Installation) -> Element<Message> {
         Column::new()
-            .push(Text::new(format!("{:?}", installation.game)).size(20))
+            .push(Text::new(installation.game.definition().title.clone()).size(20))
             .push(Text::new(format!("{:?}", self.current_step)))
             .push(Button::new(&mut self.button_states[0],
                               Text::new("Next"))
                 .on_press(Message::InstallationEvent(
-                    installation.game, 
+                    installation.id,
                     InstallationEvent::InstallerEvent(InstallerEvent::Next))))
             .into()
     }
@@ -632,4 +807,174 @@ impl Installer {
     // pub fn validator_from(installation: Installation) -> Installer {
     //     Installer::from(installation, InstallerStep::validation_steps())
     // }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::common::Language;
+    use crate::library::Library;
+    use crate::reg::backend::{test_registry, RegData, RegistryBackend};
+    use crate::reg::elevation::{RegistrationMode, virtual_store_key};
+    use super::*;
+
+    /// A complete installation in a temporary directory. Its userdata (with Options.ini) is in a temporary %APPDATA%.
+    fn installation(game: &str, checksum: &str, dir: &TempDir) -> Installation {
+        test_registry().set_appdata_path(&dir.path().join("AppData"));
+        let mut installation = Installation::defaults(Game::from_id(game).unwrap());
+        let install_path = dir.path().join(game);
+        create_dir_all(&install_path).unwrap();
+        installation.path = install_path.canonicalize().unwrap().to_str().unwrap().to_string();
+        installation.checksum = checksum.to_string();
+        installation.sync_fingerprint = checksum.chars().rev().collect();
+        installation.set_attribute(&InstallationAttribute::ERGC, String::from("ABCD-1234-EFGH-5678-IJKL")).unwrap();
+        installation.set_attribute(&InstallationAttribute::Resolution, String::from("1920x1080")).unwrap();
+        installation.is_complete = true;
+        installation.write_options().unwrap();
+        installation
+    }
+
+    fn assert_loads_as(installation: &Installation) {
+        let loaded = Installation::load(&installation.game).unwrap();
+        assert_eq!(loaded.game, installation.game);
+        assert_eq!(loaded.checksum, installation.checksum);
+        assert_eq!(loaded.sync_fingerprint, installation.sync_fingerprint);
+        assert_eq!(loaded.path, installation.path);
+        assert_eq!(loaded.ergc, installation.ergc);
+        assert_eq!(loaded.language, installation.language);
+        assert_eq!(loaded.get_userdata_path(), installation.get_userdata_path());
+        assert_eq!(loaded.get_resolution_string(), installation.get_resolution_string());
+        assert!(loaded.is_complete);
+    }
+
+    #[test]
+    fn load_without_registration_fails() {
+        assert!(Installation::load(&Game::from_id("BFME2").unwrap()).is_err());
+    }
+
+    #[test]
+    fn register_and_load() {
+        let dir = TempDir::new().unwrap();
+        let installation = installation("BFME2", "0123456789abcdef0123456789abcdef", &dir);
+        let report = Installer::register(&installation).unwrap();
+        assert!(report.is_complete());
+        assert_loads_as(&installation);
+    }
+
+    #[test]
+    fn register_and_load_expansion_with_language() {
+        let dir = TempDir::new().unwrap();
+        let mut installation = installation("ROTWK", "fedcba9876543210fedcba9876543210", &dir);
+        installation.language = Language::EnglishUK;
+        Installer::register(&installation).unwrap();
+        assert_loads_as(&installation);
+        // The base game's registration is separate
+        assert!(Installation::load(&Game::from_id("BFME2").unwrap()).is_err());
+    }
+
+    #[test]
+    fn load_fails_with_unknown_language() {
+        let dir = TempDir::new().unwrap();
+        let installation = installation("ROTWK", "99999999999999999999999999999999", &dir);
+        Installer::register(&installation).unwrap();
+        let locale = installation.game.definition().registry.locale.as_ref().unwrap();
+        test_registry().set_value(Hive::LocalMachine, &locale.key, &locale.name, &RegData::Str(String::from("xx"))).unwrap();
+        assert!(Installation::load(&installation.game).is_err());
+    }
+
+    #[test]
+    fn activate_replaces_registration() {
+        let dir = TempDir::new().unwrap();
+        let first = installation("BFME2", "11111111111111111111111111111111", &dir);
+        let mut second = installation("BFME2", "22222222222222222222222222222222", &dir);
+        second.path = dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
+        Installer::register(&first).unwrap();
+        Installer::activate(&second).unwrap();
+        assert_loads_as(&second);
+        Installer::activate(&first).unwrap();
+        assert_loads_as(&first);
+    }
+
+    #[test]
+    fn uninstall_restores_previous_registration() {
+        let dir = TempDir::new().unwrap();
+        let retail = installation("BFME2", "33333333333333333333333333333333", &dir);
+        let mut second = installation("BFME2", "44444444444444444444444444444444", &dir);
+        second.path = dir.path().canonicalize().unwrap().to_str().unwrap().to_string();
+        Installer::register(&retail).unwrap();
+        Installer::register(&second).unwrap();
+        assert!(reg::backup::has_backup(&second.game, &second.checksum));
+
+        let mut library = Library::default();
+        let retail_id = library.add(retail.clone());
+        let second_id = library.add(second.clone());
+        library.set_active(second_id);
+        let install_path = &second.game.definition().registry.install_path;
+        test_registry().set_value(Hive::LocalMachine, &install_path.key, "Retail", &RegData::UInt(1)).unwrap();
+
+        Installer::uninstall(&second, true, vec![]).unwrap();
+        assert_loads_as(&retail);
+        assert!(!reg::backup::has_backup(&second.game, &second.checksum));
+        // Values which aren't defined are kept
+        assert_eq!(test_registry().get_value(Hive::LocalMachine, &install_path.key, "Retail").unwrap(), RegData::UInt(1));
+
+        library.remove(second_id);
+        library.refresh_active_game(second.game);
+        assert_eq!(library.active(second.game).map(|inst| inst.id), Some(retail_id));
+        Installer::uninstall(&retail, true, vec![]).unwrap();
+        library.refresh_active_game(retail.game);
+        assert!(library.active(retail.game).is_none());
+    }
+
+    #[test]
+    fn user_mode_registration_and_uninstall() {
+        let dir = TempDir::new().unwrap();
+        let mut installation = installation("BFME2", "12121212121212121212121212121212", &dir);
+        installation.registration_mode = RegistrationMode::User;
+        let report = Installer::register(&installation).unwrap();
+        assert!(!report.pending.is_empty());
+        assert!(report.pending.iter().all(|value| value.hive == "HKLM" && value.virtualized));
+        assert!(report.reg_file.unwrap().exists());
+        assert!(Installation::load(&installation.game).is_err());
+        assert!(reg::health::check(&installation).unwrap().is_empty());
+
+        let pending = &report.pending[0];
+        let (hive, key) = virtual_store_key(Hive::LocalMachine, &pending.key).unwrap();
+        assert!(test_registry().get_value(hive, &key, &pending.name).is_ok());
+        Installer::uninstall(&installation, true, vec![]).unwrap();
+        assert!(test_registry().get_value(hive, &key, &pending.name).is_err());
+    }
+
+    #[test]
+    fn uninstall_inactive_keeps_registration() {
+        let dir = TempDir::new().unwrap();
+        let active = installation("BFME2", "55555555555555555555555555555555", &dir);
+        let inactive = installation("BFME2", "66666666666666666666666666666666", &dir);
+        Installer::register(&active).unwrap();
+        let install_path = PathBuf::from(&inactive.path);
+        Installer::uninstall(&inactive, false, vec![install_path.clone()]).unwrap();
+        assert!(!install_path.exists());
+        assert_loads_as(&active);
+    }
+
+    #[test]
+    fn uninstall_keeps_userdata_in_use() {
+        let dir = TempDir::new().unwrap();
+        let installation = installation("BFME2", "77777777777777777777777777777777", &dir);
+        let userdata_path = installation.get_userdata_path().unwrap();
+        std::fs::write(Path::new(&installation.path).join("checksums.txt"), "").unwrap();
+
+        let directories = Installer::uninstall_directories(&installation, true, &[]).unwrap();
+        assert!(directories.contains(&PathBuf::from(&userdata_path)));
+        let directories = Installer::uninstall_directories(&installation, true, &[userdata_path.clone()]).unwrap();
+        assert_eq!(directories, vec![PathBuf::from(&installation.path)]);
+        let directories = Installer::uninstall_directories(&installation, false, &[]).unwrap();
+        assert_eq!(directories, vec![PathBuf::from(&installation.path)]);
+    }
+
+    #[test]
+    fn uninstall_refuses_foreign_directories() {
+        let dir = TempDir::new().unwrap();
+        let installation = installation("BFME2", "88888888888888888888888888888888", &dir);
+        assert!(Installer::uninstall_directories(&installation, false, &[]).is_err());
+    }
+}
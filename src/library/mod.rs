@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::common::{Game, Installation, InstallationId};
+
+/// All installations known to the manager, any number per game (persisted as part of the config)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "LibraryFile", into = "LibraryFile")]
+pub struct Library {
+    installations: Vec<Installation>,
+    next_id: InstallationId,
+    /// The installation the registry currently points at, per game
+    active: HashMap<Game, InstallationId>,
+    /// Installations of games without a definition (e.g. a removed user definition). They aren't shown,
+    /// but are saved again so they come back with the definition.
+    unknown_games: Vec<Value>
+}
+
+/// The library as stored in config.json
+#[derive(Serialize, Deserialize)]
+struct LibraryFile {
+    installations: Vec<Value>,
+    next_id: InstallationId
+}
+
+impl TryFrom<LibraryFile> for Library {
+    type Error = String;
+
+    fn try_from(file: LibraryFile) -> Result<Self, Self::Error> {
+        let mut library = Library { next_id: file.next_id, ..Library::default() };
+        for value in file.installations {
+            match value.get("game").and_then(Value::as_str) {
+                Some(id) if Game::from_id(id).is_none() => {
+                    println!("WARNING: Keeping installation of unknown game {} without showing it", id);
+                    library.unknown_games.push(value);
+                },
+                _ => library.installations.push(serde_json::from_value(value).map_err(|e| e.to_string())?)
+            }
+        }
+        Ok(library)
+    }
+}
+
+impl From<Library> for LibraryFile {
+    fn from(library: Library) -> Self {
+        let installations = library.installations.iter()
+            .map(|inst| serde_json::to_value(inst).expect("Could not serialize installation"))
+            .chain(library.unknown_games)
+            .collect();
+        LibraryFile { installations, next_id: library.next_id }
+    }
+}
+
+impl Library {
+
+    pub fn installations(&self) -> &Vec<Installation> {
+        &self.installations
+    }
+
+    pub fn get(&self, id: InstallationId) -> Option<&Installation> {
+        self.installations.iter().find(|inst| inst.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: InstallationId) -> Option<&mut Installation> {
+        self.installations.iter_mut().find(|inst| inst.id == id)
+    }
+
+    /// Adds the installation with a new id and returns the id
+    pub fn add(&mut self, mut installation: Installation) -> InstallationId {
+        installation.id = self.next_id;
+        self.next_id += 1;
+        self.installations.push(installation);
+        self.next_id - 1
+    }
+
+    /// Adds the installation, changing its install path if another installation uses it already
+    pub fn add_unique(&mut self, mut installation: Installation) -> InstallationId {
+        let base_path = installation.path.clone();
+        let mut n = 1;
+        while self.installations.iter().any(|inst| inst.path.to_lowercase() == installation.path.to_lowercase()) {
+            n += 1;
+            installation.path = format!("{} ({})", base_path, n);
+        }
+        self.add(installation)
+    }
+
+    pub fn remove(&mut self, id: InstallationId) -> Option<Installation> {
+        self.active.retain(|_, active_id| *active_id != id);
+        let i = self.installations.iter().position(|inst| inst.id == id)?;
+        Some(self.installations.remove(i))
+    }
+
+    /// Userdata directories (of all profiles) of the installations other than the given one
+    pub fn userdata_paths_except(&self, id: InstallationId) -> Vec<String> {
+        self.installations.iter()
+            .filter(|inst| inst.id != id)
+            .flat_map(|inst| inst.profile_userdata_paths())
+            .collect()
+    }
+
+    pub fn active(&self, game: Game) -> Option<&Installation> {
+        self.active.get(&game).and_then(|id| self.get(*id))
+    }
+
+    pub fn is_active(&self, id: InstallationId) -> bool {
+        self.active.values().any(|active_id| *active_id == id)
+    }
+
+    pub fn set_active(&mut self, id: InstallationId) {
+        if let Some(game) = self.get(id).map(|inst| inst.game) {
+            self.active.insert(game, id);
+        }
+    }
+
+    /// Determines the active installations from the registry. Registered installations which aren't
+    /// in the library yet (e.g. made by earlier versions of the manager) are added.
+    pub fn refresh_active(&mut self) {
+        self.active.clear();
+        for game in Game::all() {
+            let registered = match Installation::load(&game) {
+                Ok(installation) => installation,
+                Err(_) => continue
+            };
+            let id = match self.find_registered(&registered) {
+                Some(id) => id,
+                None => {
+                    println!("Adding registered {} installation at {} to the library", game, registered.path);
+                    self.add(registered)
+                }
+            };
+            self.active.insert(game, id);
+        }
+    }
+
+    /// Determines the game's active installation from the registry (e.g. after the registry has been restored).
+    /// Unlike `refresh_active`, installations which aren't in the library are not added.
+    pub fn refresh_active_game(&mut self, game: Game) {
+        match Installation::load(&game).ok().and_then(|registered| self.find_registered(&registered)) {
+            Some(id) => self.active.insert(game, id),
+            None => self.active.remove(&game)
+        };
+    }
+
+    fn find_registered(&self, registered: &Installation) -> Option<InstallationId> {
+        self.installations.iter()
+            .find(|inst| inst.game == registered.game
+                && inst.checksum == registered.checksum
+                && inst.path.to_lowercase() == registered.path.to_lowercase())
+            .map(|inst| inst.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installations_of_unknown_games_are_kept() {
+        let mut library = Library::default();
+        library.add(Installation::defaults(Game::from_id("BFME2").unwrap()));
+        let mut value = serde_json::to_value(&library).unwrap();
+        let mut unknown = value["installations"][0].clone();
+        unknown["game"] = Value::from("BFME1");
+        unknown["id"] = Value::from(7);
+        value["installations"].as_array_mut().unwrap().push(unknown.clone());
+        value["next_id"] = Value::from(8);
+
+        let library: Library = serde_json::from_value(value).unwrap();
+        assert_eq!(library.installations().len(), 1);
+        assert!(library.get(7).is_none());
+
+        let saved = serde_json::to_value(&library).unwrap();
+        assert_eq!(saved["installations"].as_array().unwrap().len(), 2);
+        assert_eq!(saved["installations"][1], unknown);
+        assert_eq!(saved["next_id"], 8);
+    }
+}
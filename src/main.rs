@@ -4,26 +4,36 @@ mod extract;
 mod checksums;
 mod reg;
 mod components;
+mod userdata;
+mod template;
+mod library;
+mod config;
+mod games;
+mod options;
+mod relocate;
+mod maps;
 
 use std::cell::Cell;
 use std::io::{Cursor};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fs::remove_dir_all;
 use common::InstallationProgress;
 use installer::{Installer, InstallerStep};
 use md5::Md5;
-use common::{Message, Game, Installation, format_ergc, InstallationUIState};
+use common::{Message, Game, Installation, InstallationId, InstallationAttribute, format_ergc, InstallationUIState};
 use checksums::md5sum;
 
 use iced::{
     image, Column, Text, Settings, Application, executor, Command,
     Clipboard, Element, Container, Length, Button, button, Subscription,
-    Row, Space, text_input
+    Row, Space, text_input, Scrollable, scrollable, HorizontalAlignment
 };
 use regex::Regex;
 use blockies::{Classic, Ethereum};
 use crate::common::to_breakable;
 use crate::installer::InstallerEvent;
-use crate::components::InstallationView;
+use crate::components::{InstallationView, InstallationEvent, MapsView};
+use crate::config::Config;
 
 // const ICONS: Font = Font::External {
 //     name: "Icons",
@@ -43,37 +53,142 @@ pub fn main() -> iced::Result {
 
 #[derive(Debug)]
 struct Bfme2Manager {
-    installations: (Installation, Installation),
-    views: (InstallationView, InstallationView),
+    config: Config,
+    views: BTreeMap<InstallationId, InstallationView>,
     // installations: HashMap<Game, (Installation, InstallationUIState, InstallationView)>,
-    installer: Option<(Installer, Game)>,
-    bfme2_install_button: button::State,
-    rotwk_install_button: button::State,
+    installer: Option<(Installer, InstallationId)>,
+    /// One column with an add button per game
+    add_buttons: Vec<(Game, button::State)>,
+    maps_view: MapsView,
+    scroll: scrollable::State
     //inst_ui_states: Vec<(text_input::State, button::State, image::viewer::State, image::viewer::State)>,
 }
 
 impl Bfme2Manager {
     fn render_installations(&mut self) -> Element<Message> {
-        let installer_views = match self.installer {
-            Some((ref mut installer, game)) => {
-                match game {
-                    Game::BFME2 => (Some(installer.view(&self.installations.0)), None),
-                    Game::ROTWK => (None, Some(installer.view(&self.installations.1)))
-                }
-            },
-            None => (None, None)
+        let mut installer_view = match self.installer {
+            Some((ref mut installer, id)) => self.config.library.get(id)
+                .map(move |installation| (id, installer.view(installation))),
+            None => None
         };
-        
-        Column::new().height(Length::Fill)
+        let library = &self.config.library;
+        let mut elements: Vec<Vec<Element<Message>>> = self.add_buttons.iter().map(|_| vec![]).collect();
+
+        for (id, view) in self.views.iter_mut() {
+            let installation = match library.get(*id) {
+                Some(installation) => installation,
+                None => continue
+            };
+            let view_installer = match installer_view.as_ref().map(|(installer_id, _)| installer_id == id) {
+                Some(true) => installer_view.take().map(|(_, view)| view),
+                _ => None
+            };
+            // Expansions are compared together with the active (or else the first) installation of their base game
+            let base = installation.game.base_game()
+                .and_then(|base_game| library.active(base_game)
+                    .or(library.installations().iter().find(|inst| inst.game == base_game && inst.is_complete)));
+            let is_active = library.is_active(*id);
+            let peers = library.installations().iter()
+                .filter(|inst| inst.game == installation.game && inst.id != *id && inst.is_complete)
+                .map(|inst| inst.id)
+                .collect::<Vec<InstallationId>>();
+            if let Some(i) = self.add_buttons.iter().position(|(game, _)| *game == installation.game) {
+                elements[i].push(view.render(installation, view_installer, base, is_active, &peers));
+            }
+        }
+
+        let mut row = Row::new().spacing(20);
+        for ((game, add_button), elements) in self.add_buttons.iter_mut().zip(elements) {
+            let mut col = Column::new().spacing(10).width(Length::FillPortion(1))
+                .push(Button::new(add_button, Text::new(format!("Add {} installation", game))
+                        .horizontal_alignment(HorizontalAlignment::Center))
+                    .width(Length::Fill)
+                    .on_press(Message::AddInstallation(*game)));
+            for element in elements {
+                col = col.push(Space::with_height(Length::Units(20)))
+                    .push(element);
+            }
+            row = row.push(col);
+        }
+
+        let shared_maps_dir = self.config.shared_maps_dir.as_deref().unwrap_or("");
+        Scrollable::new(&mut self.scroll).height(Length::Fill)
             .push(Text::new("Installed Games").size(40))
             .push(Space::with_height(Length::Units(20)))
-            .push(Row::new().spacing(20)
-                .push(Column::new().spacing(10).width(Length::FillPortion(1))
-                    .push(self.views.0.render(&self.installations.0, installer_views.0, None)))
-                .push(Column::new().spacing(10).width(Length::FillPortion(1))
-                    .push(self.views.1.render(&self.installations.1, installer_views.1, Some(&self.installations.0)))))
+            .push(row)
+            .push(Space::with_height(Length::Units(40)))
+            .push(self.maps_view.view(shared_maps_dir))
             .into()
     }
+
+    /// Indexes the maps of the shared map folder and all complete installations in the background. Hashing
+    /// all maps takes a while, so the table is kept until the new index arrives.
+    fn refresh_maps(&mut self) -> Command<Message> {
+        let game_locations = maps::game_locations(&self.config.library, self.config.shared_maps_dir.as_deref());
+        self.maps_view.generation += 1;
+        self.maps_view.indexing = true;
+        let generation = self.maps_view.generation;
+        Command::perform(async move {
+            game_locations.into_iter()
+                .map(|(game, locations)| (game, maps::MapIndex::build(locations)))
+                .collect()
+        }, move |indexes| Message::MapsIndexed(generation, indexes))
+    }
+
+    /// Removes a directory in the background (e.g. a relocated installation's old directory)
+    fn remove_directory(path: String) -> Command<Message> {
+        Command::perform(async move {
+            println!("Removing {}...", path);
+            let result = remove_dir_all(&path).map_err(|e| e.to_string());
+            (path, result)
+        }, |(path, result)| Message::DirectoryRemoved(path, result))
+    }
+
+    fn save_config(&self) {
+        if let Err(msg) = self.config.save() {
+            println!("ERROR: Could not save config: {}", msg);
+        }
+    }
+
+    /// Switches the installation's userdata profile. The registry is updated if the game currently uses the installation.
+    fn select_profile(&mut self, id: InstallationId, profile: Option<String>, is_new: bool) {
+        let is_active = self.config.library.is_active(id);
+        let mut installation = match self.config.library.get(id) {
+            Some(installation) if !installation.in_progress => installation.clone(),
+            _ => return
+        };
+        // The switch is made on a copy, which replaces the installation only if the profile could be set up
+        if is_new {
+            installation.profiles.push(profile.clone().unwrap_or_default());
+        }
+        installation.profile = profile;
+        let result = userdata::ensure_profile(&installation)
+            .and_then(|_| match is_active {
+                true => Installer::activate(&installation).map(|_| ()),
+                false => Ok(())
+            });
+        match result {
+            Ok(()) => {
+                if let Some(current) = self.config.library.get_mut(id) {
+                    *current = installation;
+                }
+                self.save_config();
+            },
+            Err(msg) => {
+                let msg = format!("Could not switch to profile {}: {}", installation.userdata_leaf_name(), msg);
+                println!("ERROR: {}", msg);
+                if let Some(view) = self.views.get_mut(&id) {
+                    view.set_profile_message(msg);
+                }
+            }
+        }
+    }
+
+    fn remove_installation(&mut self, id: InstallationId) {
+        self.config.library.remove(id);
+        self.views.remove(&id);
+        self.save_config();
+    }
 }
 
 impl Application for Bfme2Manager {
@@ -82,23 +197,33 @@ impl Application for Bfme2Manager {
     type Flags = ();
 
     fn new(_flags: Self::Flags) -> (Self, Command<Self::Message>) {
-        let installations = (
-            Installation::load(&Game::BFME2).unwrap_or_else(|_| Installation::defaults(Game::BFME2)),
-            Installation::load(&Game::ROTWK).unwrap_or_else(|_| Installation::defaults(Game::ROTWK)),
-        );
-        println!("Installations: {:#?}", installations);
-        let (inst1, inst2) = installations.clone();
+        let mut config = Config::load();
+        // Start with one (not yet installed) installation per game
+        for game in Game::all() {
+            if !config.library.installations().iter().any(|inst| inst.game == game) {
+                let installation = config.new_installation(game);
+                config.library.add_unique(installation);
+            }
+        }
+        println!("Installations: {:#?}", config.library.installations());
+        let views = config.library.installations().iter()
+            .map(|inst| (inst.id, InstallationView::new(inst.id, inst.game)))
+            .collect::<BTreeMap<InstallationId, InstallationView>>();
+        let mut manager = Bfme2Manager {
+            config,
+            views,
+            //bfme2_view: InstallationView::new(),
+            installer: None,
+            add_buttons: Game::all().into_iter().map(|game| (game, button::State::default())).collect(),
+            maps_view: MapsView::new(),
+            scroll: scrollable::State::default()
+            //inst_ui_states
+        };
+        manager.save_config();
+        let command = manager.refresh_maps();
         (
-            Bfme2Manager {
-                installations: (installations.0, installations.1),
-                views: (InstallationView::new(inst1.game), InstallationView::new(inst2.game)),
-                //bfme2_view: InstallationView::new(),
-                installer: None,
-                bfme2_install_button: button::State::default(),
-                rotwk_install_button: button::State::default(),
-                //inst_ui_states
-            },
-            Command::none()
+            manager,
+            command
         )
     }
 
@@ -108,75 +233,383 @@ impl Application for Bfme2Manager {
 
     fn update(&mut self, message: Self::Message, _clipboard: &mut Clipboard) -> Command<Self::Message> {
         match message {
-            Message::StartInstallation(game)|Message::StartValidation(game) => {
-                self.views.0.loose_focus();
-                self.views.1.loose_focus();
-                if self.installations.0.in_progress || self.installations.1.in_progress {
+            Message::StartInstallation(id)|Message::StartValidation(id)|Message::StartAdoption(id)|Message::StartRelocation(id, _) => {
+                for view in self.views.values_mut() {
+                    view.loose_focus();
+                }
+                if self.config.library.installations().iter().any(|inst| inst.in_progress) {
                     println!("There is already an installation in progress!");
                     return Command::none();
                 }
                 let steps = match message {
                     Message::StartInstallation(_) => InstallerStep::installation_steps(),
                     Message::StartValidation(_) => InstallerStep::validation_steps(),
+                    Message::StartAdoption(_) => InstallerStep::adoption_steps(),
+                    Message::StartRelocation(..) => InstallerStep::relocation_steps(self.config.library.is_active(id)),
                     _ => vec![InstallerStep::Inactive]
                 };
                 println!("steps: {:#?}", steps);
-                let installation = match game {
-                    Game::BFME2 => &mut self.installations.0,
-                    Game::ROTWK => &mut self.installations.1
+                let installation = match self.config.library.get_mut(id) {
+                    Some(installation) => installation,
+                    None => return Command::none()
+                };
+                if let Message::StartAdoption(_) = message {
+                    if installation.ergc.is_empty() {
+                        if let Some(ergc) = Installation::registered_ergc(&installation.game) {
+                            println!("Using the registered ERGC for the adopted installation");
+                            installation.ergc = ergc;
+                        }
+                    }
+                }
+                let mut installer = match message {
+                    Message::StartRelocation(_, ref target) => Installer::relocation(installation, target.clone(), steps.contains(&InstallerStep::Register)),
+                    _ => Installer::new(steps)
                 };
-                let mut installer = Installer::new(steps);
                 installer.proceed(&installation);
-                self.installer = Some((installer, installation.game));
+                self.installer = Some((installer, installation.id));
                 installation.in_progress = true;
+                if let Some(source) = installation.install_source.clone() {
+                    self.config.add_source(&source);
+                    self.save_config();
+                }
                 // view.set_installer(&mut installer);
                 Command::none()
             },
-            Message::Progressed((id, progress)) => {
+            Message::Progressed((_, progress)) => {
                 match self.installer {
-                    Some((ref mut installer, game)) => {
-                        match game {
-                            Game::BFME2 => installer.on_progress(&self.installations.0, progress),
-                            Game::ROTWK => installer.on_progress(&self.installations.1, progress)
+                    Some((ref mut installer, id)) => {
+                        match self.config.library.get(id) {
+                            Some(installation) => installer.on_progress(installation, progress),
+                            None => Command::none()
                         }
-                        
                     },
                     None => Command::none()
                 }
             },
-            Message::InstallationEvent(Game::BFME2, event) => {
-                self.views.0.update(&self.installations.0, event)
+            Message::InstallationEvent(id, InstallationEvent::InstallerEvent(event)) => {
+                match (&mut self.installer, self.config.library.get(id)) {
+                    (Some((ref mut installer, installer_id)), Some(installation)) if *installer_id == id =>
+                        installer.update(installation, event),
+                    _ => Command::none()
+                }
             }
-            Message::InstallationEvent(Game::ROTWK, event) => {
-                self.views.1.update(&self.installations.1, event)
-            },
-            Message::AttributeUpdate(game, attr, value) => {
-                match game {
-                    Game::BFME2 => {self.installations.0.set_attribute(&attr, value)},
-                    Game::ROTWK => {self.installations.1.set_attribute(&attr, value)}
-                }.expect("Error while updating installation attribute");
+            Message::InstallationEvent(id, event) => {
+                let backups_changed = matches!(event, InstallationEvent::UserdataBackupDone(_));
+                let command = match (self.views.get_mut(&id), self.config.library.get(id)) {
+                    (Some(view), Some(installation)) => view.update(installation, event),
+                    _ => Command::none()
+                };
+                // The other installations of the game list the same backups
+                if backups_changed {
+                    for view in self.views.values_mut() {
+                        view.refresh_backups();
+                    }
+                }
+                command
+            }
+            Message::AttributeUpdate(id, attr, value) => {
+                if let Some(installation) = self.config.library.get_mut(id) {
+                    if let Err(msg) = installation.set_attribute(&attr, value) {
+                        println!("ERROR: {}", msg);
+                        return Command::none();
+                    }
+                    // Remember the choices as defaults for new installations
+                    let preferences = &mut self.config.preferences;
+                    match attr {
+                        InstallationAttribute::Language => preferences.language = installation.language,
+                        InstallationAttribute::Resolution => {
+                            preferences.resolution = installation.get_resolution_string();
+                            if installation.is_complete {
+                                if let Err(msg) = installation.write_options() {
+                                    println!("ERROR: Could not update Options.ini: {}", msg);
+                                }
+                            }
+                        },
+                        _ => {}
+                    }
+                    self.save_config();
+                }
                 Command::none()
 
             }
+            Message::AddInstallation(game) => {
+                let installation = self.config.new_installation(game);
+                let id = self.config.library.add_unique(installation);
+                self.views.insert(id, InstallationView::new(id, game));
+                self.save_config();
+                Command::none()
+            }
+            Message::RemoveInstallation(id) => {
+                if self.config.library.get(id).map(|inst| !inst.in_progress).unwrap_or(false) {
+                    self.remove_installation(id);
+                }
+                Command::none()
+            }
+            Message::RegistrationModeChanged(id, mode) => {
+                if let Some(installation) = self.config.library.get_mut(id) {
+                    installation.registration_mode = mode;
+                    self.config.preferences.registration_mode = mode;
+                    self.save_config();
+                }
+                Command::none()
+            }
+            Message::CompareInstallations(id, other) => {
+                match (self.config.library.get(id), self.config.library.get(other)) {
+                    (Some(installation), Some(other_installation)) => {
+                        let (path, other_path) = (installation.path.clone(), other_installation.path.clone());
+                        Command::perform(async move {
+                            checksums::report::diff_installations(&path, &other_path)
+                        }, move |result| Message::InstallationEvent(id, InstallationEvent::ComparisonDone(other, result)))
+                    },
+                    _ => Command::none()
+                }
+            }
+            Message::ValidationComplete(id, checksum, sync_fingerprint, report) => {
+                if let Some(view) = self.views.get_mut(&id) {
+                    view.set_validation_report(report);
+                }
+                // After a relocation the checksum has to be the same as before. A copied installation's old
+                // directory is removed once that's confirmed, or else used again instead of the copy.
+                let (verified, relocation_source) = match self.installer {
+                    Some((ref mut installer, installer_id)) if installer_id == id =>
+                        (installer.verify_checksum(&checksum), installer.take_relocation_source()),
+                    _ => (Ok(()), None)
+                };
+                let (progress, cleanup) = match (verified, relocation_source) {
+                    (Ok(()), Some(source)) => (InstallationProgress::Finished, Some(source)),
+                    (Ok(()), None) => (InstallationProgress::Finished, None),
+                    (Err(msg), Some(source)) => match self.config.library.get_mut(id) {
+                        Some(installation) => {
+                            let msg = format!("{} The installation stays at {}, the copy is removed.", msg, source);
+                            let copy = std::mem::replace(&mut installation.path, source);
+                            self.save_config();
+                            (InstallationProgress::Errored(msg), Some(copy))
+                        },
+                        None => (InstallationProgress::Errored(msg), None)
+                    },
+                    (Err(msg), None) => (InstallationProgress::Errored(msg), None)
+                };
+                let keeps_checksum = cleanup.is_some() && matches!(progress, InstallationProgress::Errored(_));
+                let userdata_in_use = self.config.library.userdata_paths_except(id);
+                if let (false, Some(installation)) = (keeps_checksum, self.config.library.get_mut(id)) {
+                    let previous = installation.clone();
+                    installation.set_attribute(&InstallationAttribute::Checksum, checksum)
+                        .and_then(|_| installation.set_attribute(&InstallationAttribute::SyncFingerprint, sync_fingerprint))
+                        .expect("Error while updating installation attribute");
+                    // The names of the profiles' userdata directories contain the checksum
+                    if previous.checksum != installation.checksum && !previous.checksum.is_empty() && !installation.profiles.is_empty() {
+                        let msg = match userdata::migrate_profiles(&previous, installation, &userdata_in_use) {
+                            Ok(kept) if kept.is_empty() => String::from("The checksum changed, the profiles' userdata has been moved along"),
+                            Ok(kept) => format!("The checksum changed. The userdata of {} is used by another installation as well and has been left at the old checksum.", kept.join(", ")),
+                            Err(msg) => format!("The checksum changed, but the profiles' userdata could not be moved along: {}", msg)
+                        };
+                        println!("{}", msg);
+                        if let Some(view) = self.views.get_mut(&id) {
+                            view.set_profile_message(msg);
+                        }
+                    }
+                    self.save_config();
+                }
+                // The installer waits for the checksum before registering the installation
+                let command = match self.installer {
+                    Some((ref mut installer, installer_id)) if installer_id == id => {
+                        match self.config.library.get(id) {
+                            Some(installation) => installer.on_progress(installation, progress),
+                            None => Command::none()
+                        }
+                    },
+                    _ => Command::none()
+                };
+                match cleanup {
+                    Some(path) => Command::batch(vec![command, Self::remove_directory(path)]),
+                    None => command
+                }
+            }
+            Message::DirectoryRemoved(path, result) => {
+                match result {
+                    Ok(()) => println!("Removed {}", path),
+                    Err(msg) => println!("ERROR: Could not remove {}: {}", path, msg)
+                }
+                Command::none()
+            }
+            Message::InstallationRelocated(id, path) => {
+                if let Some(installation) = self.config.library.get_mut(id) {
+                    println!("Moved installation {} from {} to {}", id, installation.path, path);
+                    installation.path = path;
+                    self.save_config();
+                }
+                match self.installer {
+                    Some((ref mut installer, installer_id)) if installer_id == id => {
+                        match self.config.library.get(id) {
+                            Some(installation) => installer.on_progress(installation, InstallationProgress::Finished),
+                            None => Command::none()
+                        }
+                    },
+                    _ => Command::none()
+                }
+            }
+            Message::UserdataMigrated(id, result) => {
+                let progress = match result {
+                    Ok(resolution) => {
+                        if let (Some((x, y)), Some(installation)) = (resolution, self.config.library.get_mut(id)) {
+                            match installation.set_attribute(&InstallationAttribute::Resolution, format!("{}x{}", x, y)) {
+                                Ok(()) => self.save_config(),
+                                Err(msg) => println!("ERROR: {}", msg)
+                            }
+                        }
+                        InstallationProgress::Finished
+                    },
+                    Err(msg) => InstallationProgress::Errored(msg)
+                };
+                match self.installer {
+                    Some((ref mut installer, installer_id)) if installer_id == id => {
+                        match self.config.library.get(id) {
+                            Some(installation) => installer.on_progress(installation, progress),
+                            None => Command::none()
+                        }
+                    },
+                    _ => Command::none()
+                }
+            }
             // Message::InstallerEvent(event) => {
             //     self.installer.as_mut().unwrap().update(event)
             // }
-            Message::InstallationComplete(game) => {
-                match game {
-                    Game::BFME2 => {
-                        self.installations.0.is_complete = true;
-                        self.installations.0.in_progress = false;
-                    },
-                    Game::ROTWK => {
-                        self.installations.1.is_complete = true;
-                        self.installations.1.in_progress = false;
-                    },
+            Message::InstallationComplete(id) => {
+                match &self.installer {
+                    Some((installer, installer_id)) if *installer_id == id && installer.current_step == InstallerStep::Done => {},
+                    _ => {
+                        println!("WARNING: Installation {} hasn't finished, not marking it complete", id);
+                        return Command::none();
+                    }
+                }
+                if let Some(installation) = self.config.library.get_mut(id) {
+                    installation.is_complete = true;
+                    installation.in_progress = false;
+                }
+                // Installation and validation both end with registering the installation
+                let registered = self.installer.as_ref().map(|(installer, _)| installer.registers()).unwrap_or(true);
+                if registered {
+                    self.config.library.set_active(id);
                 }
+                self.save_config();
                 //self.installations.insert(game, (data, InstallationUIState::new(), InstallationView::new()));
                 //self.inst_ui_states.push((text_input::State::default(), button::State::default(), image::viewer::State::new(), image::viewer::State::new()));
                 self.installer = None;
+                // Installing backs up the userdata it replaces
+                for view in self.views.values_mut() {
+                    view.refresh_backups();
+                }
+                self.refresh_maps()
+            }
+            Message::InstallationFailed(id) => {
+                // The installation keeps the state it had before the installer ran. A copy made by a relocation
+                // which failed before the validation finished is removed.
+                let relocation_source = match self.installer {
+                    Some((ref mut installer, installer_id)) if installer_id == id => {
+                        let source = installer.take_relocation_source();
+                        self.installer = None;
+                        source
+                    },
+                    _ => None
+                };
+                let mut command = Command::none();
+                if let Some(installation) = self.config.library.get_mut(id) {
+                    installation.in_progress = false;
+                    if let Some(source) = relocation_source {
+                        command = Self::remove_directory(std::mem::replace(&mut installation.path, source));
+                    }
+                }
+                self.save_config();
+                command
+            }
+            Message::AddProfile(id, name) => {
+                self.select_profile(id, Some(name), true);
+                Command::none()
+            }
+            Message::SelectProfile(id, profile) => {
+                self.select_profile(id, profile, false);
+                Command::none()
+            }
+            Message::InstallationActivated(id) => {
+                self.config.library.set_active(id);
+                self.save_config();
                 Command::none()
             }
+            Message::StartUninstallation(id, remove_userdata) => {
+                if self.config.library.installations().iter().any(|inst| inst.in_progress) {
+                    println!("There is already an installation in progress!");
+                    return Command::none();
+                }
+                let is_active = self.config.library.is_active(id);
+                let userdata_in_use = self.config.library.userdata_paths_except(id);
+                let installation = match self.config.library.get_mut(id) {
+                    Some(installation) => installation,
+                    None => return Command::none()
+                };
+                match Installer::uninstall_directories(installation, remove_userdata, &userdata_in_use) {
+                    Ok(directories) => {
+                        installation.in_progress = true;
+                        let installation = installation.clone();
+                        Command::perform(async move { (id, Installer::uninstall(&installation, is_active, directories)) },
+                                         |(id, result)| Message::UninstallationComplete(id, result))
+                    },
+                    Err(msg) => {
+                        if let Some(view) = self.views.get_mut(&id) {
+                            view.set_uninstall_error(msg);
+                        }
+                        Command::none()
+                    }
+                }
+            }
+            Message::UninstallationComplete(id, result) => {
+                match result {
+                    Ok(()) => {
+                        let game = self.config.library.get(id).map(|inst| inst.game);
+                        self.remove_installation(id);
+                        // Unregistering restored the registration from before the installation (if any)
+                        if let Some(game) = game {
+                            self.config.library.refresh_active_game(game);
+                            self.save_config();
+                        }
+                        self.refresh_maps()
+                    },
+                    Err(msg) => {
+                        println!("ERROR: Uninstall failed: {}", msg);
+                        if let Some(installation) = self.config.library.get_mut(id) {
+                            installation.in_progress = false;
+                        }
+                        if let Some(view) = self.views.get_mut(&id) {
+                            view.set_uninstall_error(msg);
+                        }
+                        Command::none()
+                    }
+                }
+            }
+            Message::SharedMapsDirChanged(dir) => {
+                self.config.shared_maps_dir = Some(dir);
+                self.save_config();
+                Command::none()
+            }
+            Message::RefreshMaps => {
+                self.maps_view.result = None;
+                self.refresh_maps()
+            }
+            Message::MapsIndexed(generation, indexes) => {
+                if generation == self.maps_view.generation {
+                    self.maps_view.indexes = indexes;
+                    self.maps_view.indexing = false;
+                }
+                Command::none()
+            }
+            Message::CopyMap(map, from, to) => {
+                let view = &self.maps_view;
+                self.maps_view.result = Some(match (view.location(from), view.location(to)) {
+                    (Some(from), Some(to)) => maps::copy_map(&map, &from.maps_dir, &to.maps_dir)
+                        .map(|_| format!("Copied {} from {} to {}", map, from.label, to.label)),
+                    _ => Err(String::from("Unknown map location, refresh the maps"))
+                });
+                self.refresh_maps()
+            }
             // Message::AttributeClicked(game, id) => {
             //     match game {
             //         Game::BFME2 => self.views.0.edit_attribute(id),
@@ -194,10 +627,10 @@ impl Application for Bfme2Manager {
     fn subscription(&self) -> Subscription<Message> {
 
         let subscriptions = match &self.installer {
-            Some((installer, game)) => installer.subscriptions(match game {
-                Game::BFME2 => &self.installations.0,
-                Game::ROTWK => &self.installations.1
-            }),
+            Some((installer, id)) => match self.config.library.get(*id) {
+                Some(installation) => installer.subscriptions(installation),
+                None => vec![]
+            },
             None => vec![]
         };
 
@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+use crate::checksums::calculate_hash;
+use crate::common::{Game, Installation, InstallationId};
+use crate::library::Library;
+
+/// Where maps are stored: the shared map folder or the Maps folder of an installation's userdata
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapLocation {
+    Shared,
+    Installation(InstallationId)
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedLocation {
+    pub location: MapLocation,
+    pub label: String,
+    pub maps_dir: PathBuf
+}
+
+/// The .map files of all locations with their hashes
+#[derive(Debug, Clone, Default)]
+pub struct MapIndex {
+    pub locations: Vec<IndexedLocation>,
+    /// Path of the .map file relative to the Maps folder (e.g. "map mp fords/map mp fords.map") and its hash
+    /// per location (in the order of `locations`, None if the location doesn't have the map)
+    pub maps: BTreeMap<String, Vec<Option<String>>>,
+    /// Maps which couldn't be hashed
+    pub errors: Vec<String>
+}
+
+impl MapIndex {
+
+    pub fn build(locations: Vec<IndexedLocation>) -> MapIndex {
+        let mut maps: BTreeMap<String, Vec<Option<String>>> = BTreeMap::new();
+        let mut errors = vec![];
+        for (i, location) in locations.iter().enumerate() {
+            let (hashes, location_errors) = index(&location.maps_dir);
+            for (map, hash) in hashes {
+                maps.entry(map).or_insert_with(|| vec![None; locations.len()])[i] = Some(hash);
+            }
+            errors.extend(location_errors.into_iter().map(|e| format!("{}: {}", location.label, e)));
+        }
+        MapIndex { locations, maps, errors }
+    }
+
+    pub fn location(&self, location: MapLocation) -> Option<&IndexedLocation> {
+        self.locations.iter().find(|l| l.location == location)
+    }
+
+    /// The version of the map others are compared with: the shared folder's if it has the map, or else the
+    /// most common one. Returns the index of a location with that version and the hash.
+    pub fn reference(&self, map: &str) -> Option<(usize, String)> {
+        let hashes = self.maps.get(map)?;
+        if let Some(i) = self.locations.iter().position(|l| l.location == MapLocation::Shared) {
+            if let Some(hash) = &hashes[i] {
+                return Some((i, hash.clone()))
+            }
+        }
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        for hash in hashes.iter().flatten() {
+            *counts.entry(hash).or_insert(0) += 1;
+        }
+        let (hash, _) = counts.into_iter().max_by(|(h1, c1), (h2, c2)| c1.cmp(c2).then(h2.cmp(h1)))?;
+        let i = hashes.iter().position(|h| h.as_ref() == Some(hash))?;
+        Some((i, hash.clone()))
+    }
+}
+
+/// The Maps folder in the installation's userdata (of its current profile)
+pub fn maps_dir(installation: &Installation) -> Option<PathBuf> {
+    installation.get_userdata_path().map(|path| PathBuf::from(path).join("Maps"))
+}
+
+/// The shared map folder (if configured) and the Maps folders of the game's complete installations. Maps are
+/// only compared within a game, as BFME2 and ROTWK maps aren't interchangeable.
+pub fn locations(library: &Library, shared_maps_dir: Option<&str>, game: Game) -> Vec<IndexedLocation> {
+    let shared = shared_maps_dir
+        .filter(|dir| !dir.trim().is_empty())
+        .map(|dir| IndexedLocation {
+            location: MapLocation::Shared,
+            label: String::from("Shared"),
+            maps_dir: PathBuf::from(dir.trim())
+        });
+    shared.into_iter()
+        .chain(library.installations().iter()
+            .filter(|inst| inst.game == game && inst.is_complete)
+            .filter_map(|inst| maps_dir(inst).map(|maps_dir| IndexedLocation {
+                location: MapLocation::Installation(inst.id),
+                label: format!("{} #{}", inst.game, inst.id),
+                maps_dir
+            })))
+        .collect()
+}
+
+/// The locations to index per game, leaving out games without complete installations
+pub fn game_locations(library: &Library, shared_maps_dir: Option<&str>) -> Vec<(Game, Vec<IndexedLocation>)> {
+    Game::all().into_iter()
+        .map(|game| (game, locations(library, shared_maps_dir, game)))
+        .filter(|(_, locations)| locations.iter().any(|l| l.location != MapLocation::Shared))
+        .collect()
+}
+
+/// Hashes of all .map files below the folder, by path relative to it, and the maps which couldn't be hashed
+pub fn index(maps_dir: &Path) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut hashes = BTreeMap::new();
+    let mut errors = vec![];
+    for path in list_maps(maps_dir) {
+        let rel_path = path.strip_prefix(maps_dir).unwrap().to_string_lossy().replace("\\", "/");
+        match calculate_hash(path.clone()) {
+            Ok(hash) => { hashes.insert(rel_path, hash); },
+            Err(e) => errors.push(format!("Could not hash {}: {}", rel_path, e))
+        }
+    }
+    (hashes, errors)
+}
+
+fn list_maps(dir: &Path) -> Vec<PathBuf> {
+    match dir.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .flat_map(|e| match e.path().is_dir() {
+                true => list_maps(&e.path()),
+                false => match e.path().extension().map(|ext| ext.to_string_lossy().to_lowercase() == "map") {
+                    Some(true) => vec![e.path()],
+                    _ => vec![]
+                }
+            })
+            .collect(),
+        Err(_) => vec![]
+    }
+}
+
+/// Copies a map between Maps folders. Maps in their own folder are copied together with the other files
+/// in it (preview image, map.ini, strings), replacing the target's version of the map.
+pub fn copy_map(map: &str, from: &Path, to: &Path) -> Result<(), String> {
+    let source = from.join(map);
+    let map_dir = Path::new(map).parent().filter(|dir| !dir.as_os_str().is_empty());
+    let files = match map_dir {
+        Some(dir) => fs::read_dir(from.join(dir))
+            .map_err(|e| format!("Could not read {:?}: {}", from.join(dir), e))?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .map(|e| e.path())
+            .collect::<Vec<PathBuf>>(),
+        None => vec![source.clone()]
+    };
+    if !source.is_file() {
+        return Err(format!("{:?} doesn't exist", source))
+    }
+    for file in files {
+        let target = to.join(file.strip_prefix(from).unwrap());
+        create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::copy(&file, &target).map_err(|e| format!("Could not copy {:?}: {}", file, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::reg::backend::test_registry;
+    use super::*;
+
+    fn add_installation(library: &mut Library, game: &str, checksum: &str, is_complete: bool) -> InstallationId {
+        let mut installation = Installation::defaults(Game::from_id(game).unwrap());
+        installation.checksum = String::from(checksum);
+        installation.is_complete = is_complete;
+        library.add(installation)
+    }
+
+    #[test]
+    fn locations_are_grouped_by_game() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let mut library = Library::default();
+        let bfme2 = add_installation(&mut library, "BFME2", "11111111111111111111111111111111", true);
+        add_installation(&mut library, "BFME2", "22222222222222222222222222222222", false);
+        let rotwk = add_installation(&mut library, "ROTWK", "33333333333333333333333333333333", true);
+
+        let groups = game_locations(&library, Some("D:\\Maps"));
+        let ids = groups.iter()
+            .map(|(game, locations)| (game.id(), locations.iter().map(|l| l.location).collect::<Vec<MapLocation>>()))
+            .collect::<Vec<(&str, Vec<MapLocation>)>>();
+        assert_eq!(ids, vec![
+            ("BFME2", vec![MapLocation::Shared, MapLocation::Installation(bfme2)]),
+            ("ROTWK", vec![MapLocation::Shared, MapLocation::Installation(rotwk)])
+        ]);
+
+        // Without complete installations there is nothing to compare the shared folder with
+        assert!(game_locations(&Library::default(), Some("D:\\Maps")).is_empty());
+    }
+
+    #[test]
+    fn reference_prefers_shared_then_most_common() {
+        let dir = TempDir::new().unwrap();
+        let locations = ["shared", "a", "b", "c"].iter()
+            .enumerate()
+            .map(|(i, name)| IndexedLocation {
+                location: match i {
+                    0 => MapLocation::Shared,
+                    _ => MapLocation::Installation(i as InstallationId)
+                },
+                label: name.to_string(),
+                maps_dir: dir.path().join(name)
+            })
+            .collect::<Vec<IndexedLocation>>();
+        let write = |location: &str, map: &str, contents: &str| {
+            let path = dir.path().join(location).join(map);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write("shared", "fords/fords.map", "v2");
+        write("a", "fords/fords.map", "v1");
+        write("b", "fords/fords.map", "v1");
+        write("a", "helm.map", "old");
+        write("b", "helm.map", "new");
+        write("c", "helm.map", "new");
+
+        let index = MapIndex::build(locations);
+        assert_eq!(index.maps.keys().collect::<Vec<&String>>(), vec!["fords/fords.map", "helm.map"]);
+        assert!(index.errors.is_empty());
+        assert_eq!(index.maps["helm.map"][0], None);
+        let (i, hash) = index.reference("fords/fords.map").unwrap();
+        assert_eq!((i, Some(hash)), (0, index.maps["fords/fords.map"][0].clone()));
+        let (i, hash) = index.reference("helm.map").unwrap();
+        assert_eq!((i, Some(hash)), (2, index.maps["helm.map"][2].clone()));
+        assert_ne!(index.maps["helm.map"][1], index.maps["helm.map"][2]);
+    }
+}
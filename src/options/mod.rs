@@ -0,0 +1,317 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const OPTIONS_FILE: &str = "Options.ini";
+
+/// How the game stores a setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionKind {
+    /// `yes` / `no`
+    Bool,
+    Int,
+    /// Written with six decimals (e.g. `70.000000`)
+    Float,
+    /// Width and height separated by a space (e.g. `1024 768`)
+    Resolution,
+    Text
+}
+
+/// Keys written by the game, with the type of their value. Other keys are kept as text.
+const KNOWN_KEYS: &[(&str, OptionKind)] = &[
+    ("AllHealthBars", OptionKind::Bool),
+    ("AmbientVolume", OptionKind::Float),
+    ("AudioLOD", OptionKind::Text),
+    ("Brightness", OptionKind::Int),
+    ("FlashTutorial", OptionKind::Int),
+    ("GameSpyIPAddress", OptionKind::Text),
+    ("HasSeenLogoMovies", OptionKind::Bool),
+    ("IdealStaticGameLOD", OptionKind::Text),
+    ("MovieVolume", OptionKind::Float),
+    ("MusicVolume", OptionKind::Float),
+    ("Resolution", OptionKind::Resolution),
+    ("SFXVolume", OptionKind::Float),
+    ("ScrollFactor", OptionKind::Int),
+    ("SendDelay", OptionKind::Bool),
+    ("StaticGameLOD", OptionKind::Text),
+    ("TimesInGame", OptionKind::Int),
+    ("UseEAX3", OptionKind::Bool),
+    ("VoiceVolume", OptionKind::Float)
+];
+
+/// The known settings other than the resolution (which is an installation attribute), for editing
+pub fn editable_keys() -> Vec<&'static str> {
+    KNOWN_KEYS.iter()
+        .filter(|(_, kind)| *kind != OptionKind::Resolution)
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+fn kind_of(key: &str) -> OptionKind {
+    KNOWN_KEYS.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(key))
+        .map(|(_, kind)| *kind)
+        .unwrap_or(OptionKind::Text)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Resolution(u32, u32),
+    Text(String)
+}
+
+impl OptionValue {
+    pub fn parse(kind: OptionKind, value: &str) -> Result<OptionValue, String> {
+        let error = || format!("Invalid {:?} value '{}'", kind, value);
+        match kind {
+            OptionKind::Bool => match value.to_lowercase().as_str() {
+                "yes" => Ok(OptionValue::Bool(true)),
+                "no" => Ok(OptionValue::Bool(false)),
+                _ => Err(error())
+            },
+            OptionKind::Int => value.parse().map(OptionValue::Int).map_err(|_| error()),
+            OptionKind::Float => value.parse().map(OptionValue::Float).map_err(|_| error()),
+            OptionKind::Resolution => {
+                let v = value.split_whitespace()
+                    .map(|s| s.parse::<u32>())
+                    .collect::<Result<Vec<u32>, _>>()
+                    .map_err(|_| error())?;
+                match v.len() {
+                    2 => Ok(OptionValue::Resolution(v[0], v[1])),
+                    _ => Err(error())
+                }
+            },
+            OptionKind::Text => Ok(OptionValue::Text(value.to_string()))
+        }
+    }
+}
+
+impl Display for OptionValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(true) => write!(f, "yes"),
+            OptionValue::Bool(false) => write!(f, "no"),
+            OptionValue::Int(val) => write!(f, "{}", val),
+            OptionValue::Float(val) => write!(f, "{:.6}", val),
+            OptionValue::Resolution(x, y) => write!(f, "{} {}", x, y),
+            OptionValue::Text(val) => write!(f, "{}", val)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Line {
+    /// `Key = value`. The original line is kept so unchanged settings are written back as they were.
+    Entry { key: String, value: String, raw: Option<String> },
+    /// Empty lines, comments and anything else the game wouldn't read as a setting
+    Other(String)
+}
+
+/// The game's Options.ini (in the userdata directory). Keeps unknown keys, ordering and line endings.
+#[derive(Debug, Clone)]
+pub struct OptionsIni {
+    lines: Vec<Line>,
+    line_ending: &'static str,
+    ends_with_line_ending: bool
+}
+
+impl Default for OptionsIni {
+    fn default() -> Self {
+        OptionsIni { lines: vec![], line_ending: "\r\n", ends_with_line_ending: true }
+    }
+}
+
+impl OptionsIni {
+
+    pub fn parse(contents: &str) -> OptionsIni {
+        if contents.is_empty() {
+            return OptionsIni::default()
+        }
+        let line_ending = match contents.contains("\r\n") {
+            true => "\r\n",
+            false => "\n"
+        };
+        let mut lines = contents.split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .map(|line| match line.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() && !line.trim_start().starts_with(';') => Line::Entry {
+                    key: key.trim().to_string(),
+                    value: value.trim().to_string(),
+                    raw: Some(line.to_string())
+                },
+                _ => Line::Other(line.to_string())
+            })
+            .collect::<Vec<Line>>();
+        // The trailing line ending doesn't start another line
+        let ends_with_line_ending = contents.ends_with('\n');
+        if ends_with_line_ending {
+            lines.pop();
+        }
+        OptionsIni { lines, line_ending, ends_with_line_ending }
+    }
+
+    /// Options.ini in the userdata directory, whatever the case of its name
+    pub fn path(userdata_path: &Path) -> PathBuf {
+        fs::read_dir(userdata_path).ok()
+            .and_then(|entries| entries.filter_map(|e| e.ok())
+                .find(|e| e.file_name().to_string_lossy().eq_ignore_ascii_case(OPTIONS_FILE)))
+            .map(|e| e.path())
+            .unwrap_or(userdata_path.join(OPTIONS_FILE))
+    }
+
+    pub fn load(userdata_path: &Path) -> Result<OptionsIni, String> {
+        let path = Self::path(userdata_path);
+        fs::read_to_string(&path)
+            .map(|contents| Self::parse(&contents))
+            .map_err(|e| format!("Could not read {:?}: {}", path, e))
+    }
+
+    /// Loads Options.ini, or starts an empty one if the game hasn't written it yet
+    pub fn load_or_default(userdata_path: &Path) -> OptionsIni {
+        match Self::path(userdata_path).exists() {
+            true => Self::load(userdata_path).unwrap_or_else(|e| {
+                println!("ERROR: {}", e);
+                OptionsIni::default()
+            }),
+            false => OptionsIni::default()
+        }
+    }
+
+    pub fn save(&self, userdata_path: &Path) -> Result<(), String> {
+        let path = Self::path(userdata_path);
+        create_dir_all(userdata_path).map_err(|e| e.to_string())?;
+        File::create(&path)
+            .and_then(|mut f| f.write_all(self.to_string().as_bytes()))
+            .map_err(|e| format!("Could not write {:?}: {}", path, e))
+    }
+
+    /// The raw value of a setting
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { key: k, value, .. } if k.eq_ignore_ascii_case(key) => Some(value.as_str()),
+            _ => None
+        })
+    }
+
+    /// The value of a setting, parsed according to its kind
+    pub fn get_value(&self, key: &str) -> Option<Result<OptionValue, String>> {
+        self.get(key).map(|value| OptionValue::parse(kind_of(key), value)
+            .map_err(|e| format!("{}: {}", key, e)))
+    }
+
+    /// All settings in file order
+    pub fn values(&self) -> Vec<(String, Result<OptionValue, String>)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { key, value, .. } => Some((key.clone(), OptionValue::parse(kind_of(key), value))),
+            Line::Other(_) => None
+        }).collect()
+    }
+
+    /// Changes a setting, or appends it if the file doesn't have it yet
+    pub fn set(&mut self, key: &str, value: OptionValue) {
+        let value = value.to_string();
+        let entry = self.lines.iter_mut().find(|line| match line {
+            Line::Entry { key: k, .. } => k.eq_ignore_ascii_case(key),
+            Line::Other(_) => false
+        });
+        match entry {
+            Some(Line::Entry { value: current, raw, .. }) => {
+                if *current != value {
+                    *current = value;
+                    *raw = None;
+                }
+            },
+            _ => self.lines.push(Line::Entry { key: key.to_string(), value, raw: None })
+        }
+    }
+
+    /// Parses the value according to the setting's kind and changes the setting
+    pub fn set_parsed(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = OptionValue::parse(kind_of(key), value.trim())
+            .map_err(|e| format!("{}: {}", key, e))?;
+        self.set(key, value);
+        Ok(())
+    }
+
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        match self.get_value("Resolution") {
+            Some(Ok(OptionValue::Resolution(x, y))) => Some((x, y)),
+            _ => None
+        }
+    }
+
+    pub fn set_resolution(&mut self, (x, y): (u32, u32)) {
+        self.set("Resolution", OptionValue::Resolution(x, y))
+    }
+}
+
+impl Display for OptionsIni {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", self.line_ending)?;
+            }
+            match line {
+                Line::Entry { raw: Some(raw), .. } | Line::Other(raw) => write!(f, "{}", raw)?,
+                Line::Entry { key, value, raw: None } => write!(f, "{} = {}", key, value)?
+            }
+        }
+        match self.ends_with_line_ending && !self.lines.is_empty() {
+            true => write!(f, "{}", self.line_ending),
+            false => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: &str = "AllHealthBars = yes\r\nAmbientVolume = 70.000000\r\nAudioLOD = High\r\nBrightness = 50\r\n\
+        ; comment\r\nResolution = 1920 1080\r\nSFXVolume =  83.500000\r\nUnknownKey=some value\r\n\r\nTimesInGame = 12\r\n";
+
+    #[test]
+    fn parse_and_write_back_unchanged() {
+        assert_eq!(OptionsIni::parse(OPTIONS).to_string(), OPTIONS);
+        let unix = OPTIONS.replace("\r\n", "\n");
+        assert_eq!(OptionsIni::parse(&unix).to_string(), unix);
+        let no_final_line_ending = OPTIONS.trim_end();
+        assert_eq!(OptionsIni::parse(no_final_line_ending).to_string(), no_final_line_ending);
+        assert_eq!(OptionsIni::parse("").to_string(), "");
+    }
+
+    #[test]
+    fn typed_values() {
+        let options = OptionsIni::parse(OPTIONS);
+        assert_eq!(options.get_value("allhealthbars"), Some(Ok(OptionValue::Bool(true))));
+        assert_eq!(options.get_value("AmbientVolume"), Some(Ok(OptionValue::Float(70.0))));
+        assert_eq!(options.get_value("Brightness"), Some(Ok(OptionValue::Int(50))));
+        assert_eq!(options.get_value("UnknownKey"), Some(Ok(OptionValue::Text(String::from("some value")))));
+        assert_eq!(options.resolution(), Some((1920, 1080)));
+        assert_eq!(options.get_value("MusicVolume"), None);
+        assert!(OptionsIni::parse("Brightness = bright").get_value("Brightness").unwrap().is_err());
+    }
+
+    #[test]
+    fn values_round_trip() {
+        for (key, value) in OptionsIni::parse(OPTIONS).values() {
+            let value = value.unwrap();
+            assert_eq!(OptionValue::parse(kind_of(&key), &value.to_string()), Ok(value));
+        }
+    }
+
+    #[test]
+    fn set_changes_only_that_line() {
+        let mut options = OptionsIni::parse(OPTIONS);
+        options.set_resolution((1024, 768));
+        options.set_parsed("SFXVolume", "83.5").unwrap();
+        options.set_parsed("MusicVolume", "60").unwrap();
+        assert!(options.set_parsed("UseEAX3", "maybe").is_err());
+        assert_eq!(options.to_string(), OPTIONS.replace("1920 1080", "1024 768") + "MusicVolume = 60.000000\r\n");
+    }
+}
+
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(not(test))]
+use once_cell::sync::Lazy;
+use serde::{Serialize, Deserialize};
+#[cfg(all(not(windows), not(test)))]
+use super::wine::WineRegistry;
+
+/// Registry root keys used by the installer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hive {
+    LocalMachine,
+    CurrentUser
+}
+
+impl Hive {
+    pub fn from_name(name: &str) -> Result<Hive, String> {
+        match name {
+            "HKLM" | "HKEY_LOCAL_MACHINE" => Ok(Hive::LocalMachine),
+            "HKCU" | "HKEY_CURRENT_USER" => Ok(Hive::CurrentUser),
+            _ => Err(format!("Unknown registry hive: {}", name))
+        }
+    }
+
+    /// Short name as used in the registry definitions
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hive::LocalMachine => "HKLM",
+            Hive::CurrentUser => "HKCU"
+        }
+    }
+}
+
+/// A value as stored in the registry (the installer only uses REG_SZ and REG_DWORD)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegData {
+    Str(String),
+    UInt(u32)
+}
+
+pub trait FromRegData: Sized {
+    fn from_reg_data(data: RegData) -> io::Result<Self>;
+}
+
+impl FromRegData for String {
+    fn from_reg_data(data: RegData) -> io::Result<Self> {
+        match data {
+            RegData::Str(val) => Ok(val),
+            RegData::UInt(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected string value, got DWORD"))
+        }
+    }
+}
+
+impl FromRegData for u32 {
+    fn from_reg_data(data: RegData) -> io::Result<Self> {
+        match data {
+            RegData::UInt(val) => Ok(val),
+            RegData::Str(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected DWORD value, got string"))
+        }
+    }
+}
+
+pub trait RegistryBackend: Debug + Send + Sync {
+    fn get_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<RegData>;
+    fn set_value(&self, hive: Hive, path: &str, name: &str, value: &RegData) -> io::Result<()>;
+    fn delete_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<()>;
+
+    /// Converts a local (canonicalized) path into the path the game sees
+    fn to_registry_path(&self, path: &Path) -> String {
+        path.to_str().unwrap().replace("\\\\?\\", "")
+    }
+
+    /// Converts a path read from the registry into a local path
+    fn from_registry_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(path)
+    }
+
+    /// The directory containing the game's userdata directories
+    fn appdata_path(&self) -> PathBuf {
+        dirs::home_dir().unwrap()
+            .join("AppData").join("Roaming")
+    }
+}
+
+/// Registry kept in memory. Used on platforms without a Windows registry and for testing
+/// the registration logic. Like the Windows registry, key paths and value names are case insensitive.
+#[cfg_attr(windows, allow(dead_code))]
+#[derive(Debug, Default)]
+pub struct MemoryRegistry {
+    keys: Mutex<BTreeMap<(Hive, String), BTreeMap<String, RegData>>>,
+    /// Replaces the user's %APPDATA% (e.g. with a temporary directory in tests)
+    appdata: Mutex<Option<PathBuf>>
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemoryRegistry {
+    pub fn set_appdata_path(&self, path: &Path) {
+        *self.appdata.lock().unwrap() = Some(path.to_path_buf());
+    }
+}
+
+impl RegistryBackend for MemoryRegistry {
+    fn get_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<RegData> {
+        let keys = self.keys.lock().unwrap();
+        keys.get(&(hive, path.to_lowercase()))
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Key not found: {}", path)))?
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Value not found: {}\\{}", path, name)))
+    }
+
+    fn set_value(&self, hive: Hive, path: &str, name: &str, value: &RegData) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.entry((hive, path.to_lowercase()))
+            .or_insert_with(BTreeMap::new)
+            .insert(name.to_lowercase(), value.clone());
+        Ok(())
+    }
+
+    fn delete_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.get_mut(&(hive, path.to_lowercase()))
+            .and_then(|values| values.remove(&name.to_lowercase()))
+            .map(|_| ())
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Value not found: {}\\{}", path, name)))
+    }
+
+    fn appdata_path(&self) -> PathBuf {
+        self.appdata.lock().unwrap().clone()
+            .unwrap_or(dirs::home_dir().unwrap().join("AppData").join("Roaming"))
+    }
+}
+
+#[cfg(windows)]
+#[derive(Debug, Default)]
+pub struct WinRegistry;
+
+#[cfg(windows)]
+impl WinRegistry {
+    fn predef(hive: Hive) -> winreg::RegKey {
+        winreg::RegKey::predef(match hive {
+            Hive::LocalMachine => winreg::enums::HKEY_LOCAL_MACHINE,
+            Hive::CurrentUser => winreg::enums::HKEY_CURRENT_USER
+        })
+    }
+}
+
+#[cfg(windows)]
+impl RegistryBackend for WinRegistry {
+    fn get_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<RegData> {
+        use winreg::enums::RegType;
+        use winreg::types::FromRegValue;
+
+        let raw = Self::predef(hive).open_subkey(path)?.get_raw_value(name)?;
+        match raw.vtype {
+            RegType::REG_DWORD => Ok(RegData::UInt(u32::from_reg_value(&raw)?)),
+            RegType::REG_SZ | RegType::REG_EXPAND_SZ => Ok(RegData::Str(String::from_reg_value(&raw)?)),
+            vtype => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported value type: {:?}", vtype)))
+        }
+    }
+
+    fn set_value(&self, hive: Hive, path: &str, name: &str, value: &RegData) -> io::Result<()> {
+        let (key, _) = Self::predef(hive).create_subkey(path)?;
+        match value {
+            RegData::Str(val) => key.set_value(name, val),
+            RegData::UInt(val) => key.set_value(name, val)
+        }
+    }
+
+    fn delete_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<()> {
+        Self::predef(hive)
+            .open_subkey_with_flags(path, winreg::enums::KEY_SET_VALUE)?
+            .delete_value(name)
+    }
+}
+
+#[cfg(all(windows, not(test)))]
+static REGISTRY: Lazy<Box<dyn RegistryBackend>> = Lazy::new(|| Box::new(WinRegistry::default()));
+
+#[cfg(all(not(windows), not(test)))]
+static REGISTRY: Lazy<Box<dyn RegistryBackend>> = Lazy::new(|| match WineRegistry::default_prefix() {
+    Some(prefix) => {
+        println!("Using registry of wine prefix {:?}", prefix);
+        Box::new(WineRegistry::new(prefix))
+    },
+    None => Box::new(MemoryRegistry::default())
+});
+
+#[cfg(test)]
+thread_local! {
+    /// Tests run on their own threads, so every test starts with an empty registry
+    static TEST_REGISTRY: &'static MemoryRegistry = Box::leak(Box::new(MemoryRegistry::default()));
+}
+
+/// The registry of the current test
+#[cfg(test)]
+pub fn test_registry() -> &'static MemoryRegistry {
+    TEST_REGISTRY.with(|registry| *registry)
+}
+
+/// The registry backend for the current platform
+#[cfg(not(test))]
+pub fn registry() -> &'static dyn RegistryBackend {
+    REGISTRY.as_ref()
+}
+
+#[cfg(test)]
+pub fn registry() -> &'static dyn RegistryBackend {
+    test_registry()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_case_insensitive() {
+        let registry = MemoryRegistry::default();
+        registry.set_value(Hive::LocalMachine, "SOFTWARE\\Test", "Name", &RegData::Str(String::from("value"))).unwrap();
+        assert_eq!(registry.get_value(Hive::LocalMachine, "software\\test", "NAME").unwrap(), RegData::Str(String::from("value")));
+        assert_eq!(registry.get_value(Hive::CurrentUser, "SOFTWARE\\Test", "Name").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(registry.get_value(Hive::LocalMachine, "SOFTWARE\\Test", "Other").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn delete_value() {
+        let registry = MemoryRegistry::default();
+        registry.set_value(Hive::CurrentUser, "SOFTWARE\\Game", "a", &RegData::UInt(1)).unwrap();
+        registry.set_value(Hive::CurrentUser, "SOFTWARE\\Game", "b", &RegData::UInt(2)).unwrap();
+        registry.delete_value(Hive::CurrentUser, "SOFTWARE\\Game", "A").unwrap();
+        assert!(registry.get_value(Hive::CurrentUser, "SOFTWARE\\Game", "a").is_err());
+        assert_eq!(registry.get_value(Hive::CurrentUser, "SOFTWARE\\Game", "b").unwrap(), RegData::UInt(2));
+        assert_eq!(registry.delete_value(Hive::CurrentUser, "SOFTWARE\\Game", "a").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
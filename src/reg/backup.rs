@@ -0,0 +1,95 @@
+use std::fs::{self, File, create_dir_all};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use crate::common::{Game, app_data_dir};
+use super::{Regentries, RegData, Hive};
+use super::backend::registry;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupValue {
+    pub hive: String,
+    pub key: String,
+    pub name: String,
+    /// None if the value didn't exist before
+    pub value: Option<RegData>
+}
+
+/// Registry values as they were before the installer registered an installation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryBackup {
+    pub created: String,
+    pub values: Vec<BackupValue>
+}
+
+pub fn backup_path(game: &Game, checksum: &str) -> PathBuf {
+    app_data_dir().join("backups").join("registry")
+        .join(format!("{}_{}.json", game.to_string().to_lowercase(), checksum))
+}
+
+pub fn has_backup(game: &Game, checksum: &str) -> bool {
+    backup_path(game, checksum).exists()
+}
+
+/// Reads the current value of every key and value defined in the registry entries
+pub fn snapshot(entries: &Regentries) -> Result<RegistryBackup, String> {
+    let registry = registry();
+    let values = entries.keys.iter()
+        .flat_map(|(hive, keys)| keys.iter().map(move |(key, values)| (hive, key, values)))
+        .flat_map(|(hive, key, values)| values.keys().map(move |name| (hive, key, name)))
+        .map(|(hive, key, name)| {
+            let value = match registry.get_value(Hive::from_name(hive)?, key, name) {
+                Ok(value) => Some(value),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(format!("Could not read {}\\{}\\{}: {}", hive, key, name, e))
+            };
+            Ok(BackupValue { hive: hive.clone(), key: key.clone(), name: name.clone(), value })
+        })
+        .collect::<Result<Vec<BackupValue>, String>>()?;
+
+    Ok(RegistryBackup { created: chrono::Local::now().to_rfc3339(), values })
+}
+
+/// Saves the snapshot of the registry values as the installation's backup, unless a backup exists already.
+/// An existing backup is kept, since it holds the values from before the installation was first registered.
+pub fn ensure_backup(game: &Game, checksum: &str, backup: &RegistryBackup) -> Result<(), String> {
+    let path = backup_path(game, checksum);
+    if path.exists() {
+        return Ok(())
+    }
+    let contents = serde_json::to_string_pretty(backup).map_err(|e| e.to_string())?;
+    println!("Backing up registry values to {:?}...", path);
+    create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+    File::create(&path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+/// Writes the given values back to the registry. Values which didn't exist are removed.
+pub fn apply(backup: &RegistryBackup) -> Result<(), String> {
+    let registry = registry();
+    for value in &backup.values {
+        let hive = Hive::from_name(&value.hive)?;
+        let result = match &value.value {
+            Some(data) => registry.set_value(hive, &value.key, &value.name, data),
+            None => match registry.delete_value(hive, &value.key, &value.name) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result
+            }
+        };
+        result.map_err(|e| format!("Could not restore {}\\{}\\{}: {}", value.hive, value.key, value.name, e))?;
+    }
+    Ok(())
+}
+
+/// Restores the registry values from the installation's backup and removes the backup
+pub fn restore(game: &Game, checksum: &str) -> Result<(), String> {
+    let path = backup_path(game, checksum);
+    let backup: RegistryBackup = serde_json::from_str(&fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read backup {:?}: {}", path, e))?)
+        .map_err(|e| format!("Invalid backup {:?}: {}", path, e))?;
+
+    println!("Restoring registry values from {:?}...", path);
+    apply(&backup)?;
+    fs::remove_file(&path).map_err(|e| e.to_string())
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use crate::common::{Game, Language, app_config_dir};
+use crate::games::definitions_dir;
+use super::{Regentries, RegValue, template_data};
+
+static BFME2_DEFAULTS: &str = include_str!("bfme2.reg");
+static ROTWK_DEFAULTS: &str = include_str!("rotwk.reg");
+
+/// Location of the user's overrides for a game's registry definitions
+pub fn override_path(game: &Game) -> PathBuf {
+    app_config_dir().join("registry")
+        .join(format!("{}.reg", game.id().to_lowercase()))
+}
+
+/// The registry template named by the game's definition: a bundled one, or else a file in the games directory
+fn template(game: &Game) -> Result<Option<String>, String> {
+    let name = match &game.definition().registry_template {
+        Some(name) => name,
+        None => return Ok(None)
+    };
+    match bundled_template(name) {
+        Some(contents) => Ok(Some(contents.to_string())),
+        None => {
+            let path = definitions_dir().join(name);
+            read_to_string(&path).map(Some)
+                .map_err(|e| format!("Could not read registry template {:?} of {}: {}", path, game, e))
+        }
+    }
+}
+
+fn bundled_template(name: &str) -> Option<&'static str> {
+    match name {
+        "bfme2.reg" => Some(BFME2_DEFAULTS),
+        "rotwk.reg" => Some(ROTWK_DEFAULTS),
+        _ => None
+    }
+}
+
+/// Loads the registry template for the game and applies the user's overrides (if any)
+pub fn load(game: &Game) -> Result<Regentries, String> {
+    let override_path = override_path(game);
+    let mut entries = match template(game)? {
+        Some(contents) => Regentries::parse(&contents)
+            .map_err(|e| format!("Invalid registry template for {}: {}", game, e))?,
+        // Without a template, all of the registry definitions are in the override file
+        None if override_path.exists() => Regentries { keys: BTreeMap::new() },
+        None => return Err(format!("No registry definitions for {} (expected {:?})", game, override_path))
+    };
+
+    if let Ok(overrides) = read_to_string(&override_path) {
+        println!("Applying registry overrides from {:?}", override_path);
+        entries.apply(&overrides)
+            .map_err(|e| format!("Invalid registry definitions in {:?}: {}", override_path, e))?;
+    }
+    entries.validate(game)
+        .map_err(|e| format!("Invalid registry definitions for {}: {}", game, e))?;
+    Ok(entries)
+}
+
+impl Regentries {
+
+    pub fn parse(contents: &str) -> Result<Regentries, String> {
+        let mut entries = Regentries { keys: BTreeMap::new() };
+        entries.apply(contents)?;
+        Ok(entries)
+    }
+
+    /// Renders every template with sample data, so unknown variables and invalid DWORD templates are
+    /// reported when the definitions are loaded rather than halfway through a registration
+    pub fn validate(&self, game: &Game) -> Result<(), String> {
+        let sample_data = template_data(game, "C:\\Program Files (x86)\\Electronic Arts\\Game", "ABCD1234EFGH5678IJKL",
+                                        "0123456789abcdef0123456789abcdef", "0123456789abcdef0123456789abcdef",
+                                        &Language::default());
+        for (hive, keys) in self.keys.iter() {
+            for (key, values) in keys.iter() {
+                for (name, value) in values.iter() {
+                    value.render(&sample_data)
+                        .map_err(|e| format!("{}\\{}\\{}: {}", hive, key, name, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies .reg style definitions on top of the existing ones. Values are templates if they contain `{{`.
+    /// Like in .reg files, `[-KEY]` removes a key and `"name"=-` removes a value.
+    pub fn apply(&mut self, contents: &str) -> Result<(), String> {
+        let mut current: Option<(String, String)> = None;
+
+        for (i, line) in contents.replace("\r", "").split('\n').enumerate() {
+            let line = line.trim_start_matches('\u{feff}').trim();
+            let error = |msg: &str| format!("line {}: {}", i + 1, msg);
+
+            if line.is_empty() || line.starts_with(';') || line.starts_with("Windows Registry Editor") {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let (remove, path) = match line[1..line.len() - 1].strip_prefix('-') {
+                    Some(path) => (true, path),
+                    None => (false, &line[1..line.len() - 1])
+                };
+                let (hive, key) = path.split_once('\\')
+                    .ok_or(error("Key must start with a hive (e.g. HKEY_LOCAL_MACHINE)"))?;
+                let hive = hive_short_name(hive).ok_or(error(&format!("Unknown hive '{}'", hive)))?;
+                let hive_keys = self.keys.entry(hive.to_string()).or_insert_with(BTreeMap::new);
+                if remove {
+                    hive_keys.remove(key);
+                    current = None;
+                } else {
+                    hive_keys.entry(key.to_string()).or_insert_with(BTreeMap::new);
+                    current = Some((hive.to_string(), key.to_string()));
+                }
+                continue;
+            }
+
+            let (hive, key) = current.as_ref().ok_or(error("Value outside of a key"))?;
+            let (name, data) = parse_value(line).map_err(|e| error(&e))?;
+            let values = self.keys.get_mut(hive).unwrap().get_mut(key).unwrap();
+            match data {
+                Some(value) => values.insert(name, value),
+                None => values.remove(&name)
+            };
+        }
+        Ok(())
+    }
+}
+
+fn hive_short_name(hive: &str) -> Option<&'static str> {
+    match hive {
+        "HKEY_LOCAL_MACHINE" | "HKLM" => Some("HKLM"),
+        "HKEY_CURRENT_USER" | "HKCU" => Some("HKCU"),
+        _ => None
+    }
+}
+
+/// Parses `"name"="value"`, `"name"=dword:0000001f`, `@="default value"` and `"name"=-` (removal).
+/// DWORDs can be templates as well (e.g. `dword:{{ language_id }}`), which have to render to a hex number.
+fn parse_value(line: &str) -> Result<(String, Option<RegValue>), String> {
+    let (name, data) = if let Some(data) = line.strip_prefix("@=") {
+        (String::new(), data)
+    } else if line.starts_with('"') {
+        let end = find_closing_quote(line, 1).ok_or("Unterminated value name")?;
+        let data = line[end + 1..].strip_prefix('=').ok_or("Expected '=' after value name")?;
+        (unescape(&line[1..end]), data)
+    } else {
+        return Err(format!("Invalid value definition: {}", line))
+    };
+
+    let value = if data == "-" {
+        None
+    } else if data.starts_with('"') {
+        let end = find_closing_quote(data, 1).ok_or("Unterminated string value")?;
+        let val = unescape(&data[1..end]);
+        match val.contains("{{") {
+            true => Some(RegValue::Template(val)),
+            false => Some(RegValue::Str(val))
+        }
+    } else if let Some(dword) = data.strip_prefix("dword:") {
+        if dword.contains("{{") {
+            return Ok((name, Some(RegValue::UIntTemplate(dword.trim().to_string()))))
+        }
+        Some(RegValue::UInt(u32::from_str_radix(dword.trim(), 16).map_err(|e| e.to_string())?))
+    } else {
+        return Err(format!("Unsupported value type: {}", data))
+    };
+    Ok((name, value))
+}
+
+fn find_closing_quote(s: &str, start: usize) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip_while(|(i, _)| *i < start) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false
+        }
+    }
+    None
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push(chars.next().unwrap_or('\\')),
+            c => result.push(c)
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reg::backend::RegData;
+    use super::*;
+
+    const INSTALL_PATH: &str = "C:\\Games\\Tom & Jerry's BFME2";
+
+    fn render_all(game: &Game, language: &Language) -> BTreeMap<(String, String, String), RegData> {
+        let entries = Regentries::parse(&template(game).unwrap().unwrap()).unwrap();
+        let data = template_data(game, INSTALL_PATH, "ABCD1234EFGH5678IJKL", "0123456789abcdef0123456789abcdef",
+                                 "fedcba9876543210fedcba9876543210", language);
+        entries.keys.iter()
+            .flat_map(|(hive, keys)| keys.iter().map(move |(key, values)| (hive, key, values)))
+            .flat_map(|(hive, key, values)| values.iter().map(move |(name, value)| (hive, key, name, value)))
+            .map(|(hive, key, name, value)| ((hive.clone(), key.clone(), name.clone()), value.render(&data).unwrap()))
+            .collect()
+    }
+
+    fn value<'a>(values: &'a BTreeMap<(String, String, String), RegData>, key: &str, name: &str) -> &'a RegData {
+        values.get(&(String::from("HKLM"), format!("SOFTWARE\\WOW6432Node\\{}", key), String::from(name)))
+            .unwrap_or_else(|| panic!("{}\\{} not defined", key, name))
+    }
+
+    fn string(value: &str) -> RegData {
+        RegData::Str(String::from(value))
+    }
+
+    #[test]
+    fn render_bfme2() {
+        let game = Game::from_id("BFME2").unwrap();
+        let values = render_all(&game, &Language::EnglishUK);
+        let key = "Electronic Arts\\Electronic Arts\\The Battle for Middle-earth II";
+        assert_eq!(value(&values, key, "InstallPath"), &string(INSTALL_PATH));
+        assert_eq!(value(&values, key, "Language"), &string("english"));
+        assert_eq!(value(&values, key, "UserDataLeafName"), &string(&game.userdata_leaf_name("0123456789abcdef0123456789abcdef")));
+        assert_eq!(value(&values, &format!("{}\\ergc", key), ""), &string("ABCD1234EFGH5678IJKL"));
+        let key = "Electronic Arts\\The Battle for Middle-earth II";
+        assert_eq!(value(&values, &format!("{}\\1.0", key), "Language"), &RegData::UInt(0x13));
+        assert_eq!(value(&values, &format!("{}\\1.0", key), "LanguageName"), &string("English UK"));
+        assert_eq!(value(&values, key, "Locale"), &string("en_uk"));
+        assert_eq!(value(&values, key, "CD Drive"), &string("D:\\"));
+        assert_eq!(value(&values, "Microsoft\\Windows\\CurrentVersion\\App Paths\\lotrbfme2.exe", ""),
+                   &string(&format!("{}\\lotrbfme2.exe", INSTALL_PATH)));
+        assert_eq!(value(&values, "Electronic Arts\\BFME2 Installer\\BFME2", "sync_fingerprint"),
+                   &string("fedcba9876543210fedcba9876543210"));
+    }
+
+    #[test]
+    fn render_rotwk() {
+        let game = Game::from_id("ROTWK").unwrap();
+        let values = render_all(&game, &Language::default());
+        let key = "Electronic Arts\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king";
+        assert_eq!(value(&values, key, "InstallPath"), &string(INSTALL_PATH));
+        assert_eq!(value(&values, key, "Language"), &string("english"));
+        let key = "Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king";
+        assert_eq!(value(&values, &format!("{}\\1.0", key), "Language"), &RegData::UInt(0x13));
+        assert_eq!(value(&values, key, "Locale"), &string("en_uk"));
+    }
+
+    #[test]
+    fn bundled_definitions_are_valid() {
+        for id in ["BFME2", "ROTWK"].iter() {
+            let game = Game::from_id(id).unwrap();
+            Regentries::parse(&template(&game).unwrap().unwrap()).unwrap().validate(&game).unwrap();
+        }
+    }
+
+    #[test]
+    fn bundled_definitions_write_the_registry_layout() {
+        for id in ["BFME2", "ROTWK"].iter() {
+            let game = Game::from_id(id).unwrap();
+            let entries = Regentries::parse(&template(&game).unwrap().unwrap()).unwrap();
+            let layout = &game.definition().registry;
+            let installer_values = ["checksum", "sync_fingerprint", "install_path"].iter()
+                .map(|name| layout.installer_key.value(name));
+            for location in installer_values
+                .chain(vec![&layout.install_path, &layout.userdata_leaf_name, &layout.ergc].into_iter().cloned())
+                .chain(layout.locale.iter().cloned()) {
+                assert!(entries.keys[&location.hive].get(&location.key).map_or(false, |values| values.contains_key(&location.name)),
+                        "{}: {}\\{} is not defined", game, location.key, location.name);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_variables_are_invalid() {
+        let game = Game::from_id("BFME2").unwrap();
+        let mut entries = Regentries::parse(BFME2_DEFAULTS).unwrap();
+        entries.apply("[HKEY_CURRENT_USER\\Software\\Test]\n\"Path\"=\"{{ instal_path }}\"").unwrap();
+        assert!(entries.validate(&game).is_err());
+
+        let mut entries = Regentries::parse(BFME2_DEFAULTS).unwrap();
+        entries.apply("[HKEY_CURRENT_USER\\Software\\Test]\n\"Language\"=dword:{{ language_name }}").unwrap();
+        assert!(entries.validate(&game).is_err());
+    }
+
+    #[test]
+    fn overrides_remove_values_and_keys() {
+        let mut entries = Regentries::parse(BFME2_DEFAULTS).unwrap();
+        let key = "SOFTWARE\\WOW6432Node\\Electronic Arts\\The Battle for Middle-earth II";
+        entries.apply(&format!("[HKEY_LOCAL_MACHINE\\{}]\n\"CD Drive\"=-\n\"Region\"=\"Europe\"\n\n[-HKEY_LOCAL_MACHINE\\{}\\1.0]", key, key)).unwrap();
+        let values = &entries.keys["HKLM"][key];
+        assert!(!values.contains_key("CD Drive"));
+        assert!(matches!(&values["Region"], RegValue::Str(region) if region == "Europe"));
+        assert!(!entries.keys["HKLM"].contains_key(&format!("{}\\1.0", key)));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use crate::common::{Game, app_data_dir};
+use super::{Regentries, RegData, Hive};
+use super::backend::RegistryBackend;
+use super::export::{format_reg_file, write_reg_file};
+use serde::{Serialize, Deserialize};
+
+/// Where registry values for HKEY_LOCAL_MACHINE are written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistrationMode {
+    /// Write to HKEY_LOCAL_MACHINE (needs admin rights)
+    Admin,
+    /// Write HKEY_LOCAL_MACHINE values to the user's VirtualStore and HKEY_CURRENT_USER values as usual
+    User
+}
+
+impl Default for RegistrationMode {
+    fn default() -> Self {
+        RegistrationMode::Admin
+    }
+}
+
+/// A value which could only be written to the user's VirtualStore (or not at all)
+#[derive(Debug, Clone)]
+pub struct PendingValue {
+    pub hive: String,
+    pub key: String,
+    pub name: String,
+    pub data: RegData,
+    /// Whether the value was written to the VirtualStore instead
+    pub virtualized: bool
+}
+
+/// Outcome of a registration
+#[derive(Debug, Clone, Default)]
+pub struct RegistrationReport {
+    /// Values which still have to be imported with admin rights
+    pub pending: Vec<PendingValue>,
+    /// .reg file containing the pending values
+    pub reg_file: Option<PathBuf>
+}
+
+impl RegistrationReport {
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+pub fn is_access_denied(e: &io::Error) -> bool {
+    // ERROR_ACCESS_DENIED
+    e.kind() == io::ErrorKind::PermissionDenied || e.raw_os_error() == Some(5)
+}
+
+/// Windows redirects writes of legacy 32 bit programs (like the game) to HKLM\SOFTWARE to
+/// HKCU\Software\Classes\VirtualStore\MACHINE\SOFTWARE for users without admin rights and reads them from there.
+/// Returns None for keys outside of HKLM\SOFTWARE, which aren't virtualized.
+pub fn virtual_store_key(hive: Hive, key: &str) -> Option<(Hive, String)> {
+    match hive {
+        Hive::LocalMachine if key.to_lowercase().starts_with("software\\") =>
+            Some((Hive::CurrentUser, format!("Software\\Classes\\VirtualStore\\MACHINE\\{}", key))),
+        _ => None
+    }
+}
+
+/// The entries plus their VirtualStore counterparts, e.g. to back up everything a registration might write
+pub fn including_virtual_store(entries: &Regentries) -> Result<Regentries, String> {
+    let mut result = entries.clone();
+    for (hive, keys) in entries.keys.iter() {
+        for (key, values) in keys.iter() {
+            if let Some((virtual_hive, virtual_key)) = virtual_store_key(Hive::from_name(hive)?, key) {
+                result.keys.entry(virtual_hive.name().to_string())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(virtual_key, values.clone());
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// The entries where a registration in the given mode leaves them: in user mode, HKLM values are in the
+/// VirtualStore (values outside of HKLM\SOFTWARE can't be written at all and are left out)
+pub fn registered_entries(entries: &Regentries, mode: RegistrationMode) -> Result<Regentries, String> {
+    if mode == RegistrationMode::Admin {
+        return Ok(entries.clone())
+    }
+    let mut result = Regentries { keys: BTreeMap::new() };
+    for (hive, keys) in entries.keys.iter() {
+        let hive = Hive::from_name(hive)?;
+        for (key, values) in keys.iter() {
+            let target = match hive {
+                Hive::LocalMachine => virtual_store_key(hive, key),
+                Hive::CurrentUser => Some((hive, key.clone()))
+            };
+            if let Some((target_hive, target_key)) = target {
+                result.keys.entry(target_hive.name().to_string())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(target_key, values.clone());
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Every value a registration in the given mode might write (to back it up before and remove it on uninstall).
+/// Admin mode registrations fall back to the VirtualStore as well if HKLM turns out not to be writable.
+pub fn affected_entries(entries: &Regentries, mode: RegistrationMode) -> Result<Regentries, String> {
+    match mode {
+        RegistrationMode::Admin => including_virtual_store(entries),
+        RegistrationMode::User => registered_entries(entries, mode)
+    }
+}
+
+/// Writes a registry value. HKLM values which can't be written (or aren't written in user mode) go to the
+/// VirtualStore instead and are returned as pending, since they still have to be imported with admin rights.
+pub fn write_value(registry: &dyn RegistryBackend, hive: Hive, key: &str, name: &str, data: &RegData,
+                   mode: RegistrationMode) -> Result<Option<PendingValue>, String> {
+    let result = match (hive, mode) {
+        (Hive::LocalMachine, RegistrationMode::User) => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+        _ => {
+            println!("Write {:?} to {}", data, key);
+            registry.set_value(hive, key, name, data)
+        }
+    };
+    match result {
+        Ok(()) => Ok(None),
+        Err(e) if is_access_denied(&e) => {
+            let virtualized = match virtual_store_key(hive, key) {
+                Some((virtual_hive, virtual_key)) => {
+                    println!("Write {:?} to {}", data, virtual_key);
+                    registry.set_value(virtual_hive, &virtual_key, name, data)
+                        .map_err(|e| format!("Could not write {}\\{}: {}", virtual_key, name, e))?;
+                    true
+                },
+                None => false
+            };
+            Ok(Some(PendingValue {
+                hive: hive.name().to_string(),
+                key: key.to_string(),
+                name: name.to_string(),
+                data: data.clone(),
+                virtualized
+            }))
+        },
+        Err(e) => Err(format!("Could not write {}\\{}\\{}: {}", hive.name(), key, name, e))
+    }
+}
+
+/// Writes the pending values to %APPDATA%\BFME2 Installer\registry, to be imported by an administrator
+pub fn write_pending_reg_file(game: &Game, checksum: &str, pending: &Vec<PendingValue>) -> Result<PathBuf, String> {
+    let mut keys: BTreeMap<(String, String), Vec<(String, RegData)>> = BTreeMap::new();
+    for value in pending {
+        keys.entry((value.hive.clone(), value.key.clone()))
+            .or_insert_with(Vec::new)
+            .push((value.name.clone(), value.data.clone()));
+    }
+    let target = app_data_dir().join("registry")
+        .join(format!("{}_{}_admin.reg", game.to_string().to_lowercase(), checksum));
+    println!("Writing values which need admin rights to {:?}...", target);
+    write_reg_file(&target, &format_reg_file(&keys))?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::reg::RegValue;
+    use crate::reg::backend::MemoryRegistry;
+    use super::*;
+
+    const KEY: &str = "SOFTWARE\\WOW6432Node\\Electronic Arts\\Game";
+    const VIRTUAL_KEY: &str = "Software\\Classes\\VirtualStore\\MACHINE\\SOFTWARE\\WOW6432Node\\Electronic Arts\\Game";
+
+    /// A registry as seen by a user without admin rights: HKLM can be read, but not written
+    #[derive(Debug, Default)]
+    struct UserRegistry(MemoryRegistry);
+
+    impl RegistryBackend for UserRegistry {
+        fn get_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<RegData> {
+            self.0.get_value(hive, path, name)
+        }
+
+        fn set_value(&self, hive: Hive, path: &str, name: &str, value: &RegData) -> io::Result<()> {
+            match hive {
+                Hive::LocalMachine => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+                Hive::CurrentUser => self.0.set_value(hive, path, name, value)
+            }
+        }
+
+        fn delete_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<()> {
+            self.0.delete_value(hive, path, name)
+        }
+    }
+
+    fn path() -> RegData {
+        RegData::Str(String::from("C:\\Games\\Game"))
+    }
+
+    #[test]
+    fn admin_mode_writes_to_hklm() {
+        let registry = MemoryRegistry::default();
+        assert!(write_value(&registry, Hive::LocalMachine, KEY, "InstallPath", &path(), RegistrationMode::Admin).unwrap().is_none());
+        assert_eq!(registry.get_value(Hive::LocalMachine, KEY, "InstallPath").unwrap(), path());
+        assert!(registry.get_value(Hive::CurrentUser, VIRTUAL_KEY, "InstallPath").is_err());
+    }
+
+    #[test]
+    fn access_denied_falls_back_to_virtual_store() {
+        let registry = UserRegistry::default();
+        let pending = write_value(&registry, Hive::LocalMachine, KEY, "InstallPath", &path(), RegistrationMode::Admin)
+            .unwrap().unwrap();
+        assert!(pending.virtualized);
+        assert_eq!((pending.hive.as_str(), pending.key.as_str(), pending.name.as_str()), ("HKLM", KEY, "InstallPath"));
+        assert_eq!(registry.get_value(Hive::CurrentUser, VIRTUAL_KEY, "InstallPath").unwrap(), path());
+
+        // HKCU values don't need admin rights
+        assert!(write_value(&registry, Hive::CurrentUser, KEY, "InstallPath", &path(), RegistrationMode::Admin).unwrap().is_none());
+    }
+
+    #[test]
+    fn user_mode_skips_hklm() {
+        let registry = MemoryRegistry::default();
+        let pending = write_value(&registry, Hive::LocalMachine, KEY, "InstallPath", &path(), RegistrationMode::User)
+            .unwrap().unwrap();
+        assert!(pending.virtualized);
+        assert!(registry.get_value(Hive::LocalMachine, KEY, "InstallPath").is_err());
+        assert_eq!(registry.get_value(Hive::CurrentUser, VIRTUAL_KEY, "InstallPath").unwrap(), path());
+
+        // Only HKLM\SOFTWARE is virtualized
+        let pending = write_value(&registry, Hive::LocalMachine, "SYSTEM\\Game", "InstallPath", &path(), RegistrationMode::User)
+            .unwrap().unwrap();
+        assert!(!pending.virtualized);
+    }
+
+    #[test]
+    fn registered_entries_per_mode() {
+        let entries = Regentries::parse(&format!(
+            "[HKEY_LOCAL_MACHINE\\{}]\n\"a\"=dword:00000001\n\n[HKEY_LOCAL_MACHINE\\SYSTEM\\Game]\n\"b\"=dword:00000002\n\n[HKEY_CURRENT_USER\\Software\\Game]\n\"c\"=dword:00000003",
+            KEY)).unwrap();
+        let keys = |entries: &Regentries| entries.keys.iter()
+            .flat_map(|(hive, keys)| keys.keys().map(move |key| format!("{}\\{}", hive, key)))
+            .collect::<Vec<String>>();
+
+        let admin = registered_entries(&entries, RegistrationMode::Admin).unwrap();
+        assert_eq!(keys(&admin), keys(&entries));
+        let user = registered_entries(&entries, RegistrationMode::User).unwrap();
+        assert_eq!(keys(&user), vec![format!("HKCU\\{}", VIRTUAL_KEY), String::from("HKCU\\Software\\Game")]);
+        let affected = affected_entries(&entries, RegistrationMode::Admin).unwrap();
+        assert_eq!(keys(&affected).len(), 4);
+        assert_eq!(keys(&affected_entries(&entries, RegistrationMode::User).unwrap()), keys(&user));
+    }
+
+    #[test]
+    fn pending_values_are_written_as_utf16_reg_file() {
+        let game = Game::from_id("BFME2").unwrap();
+        let name = RegData::Str(String::from("Tolkien's \"Mittelerde\" – Ära"));
+        let pending = vec![
+            PendingValue { hive: String::from("HKLM"), key: String::from(KEY), name: String::from("InstallPath"), data: path(), virtualized: true },
+            PendingValue { hive: String::from("HKLM"), key: String::from(KEY), name: String::from("Name"), data: name.clone(), virtualized: true },
+            PendingValue { hive: String::from("HKLM"), key: format!("{}\\1.0", KEY), name: String::from("Language"), data: RegData::UInt(0x13), virtualized: true }
+        ];
+        let path = write_pending_reg_file(&game, "abababababababababababababababab", &pending).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[..2], &[0xFF, 0xFE]);
+        let contents = String::from_utf16(&bytes.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<u16>>()).unwrap();
+
+        let entries = Regentries::parse(&contents).unwrap();
+        let values = &entries.keys["HKLM"][KEY];
+        assert!(matches!(&values["InstallPath"], RegValue::Str(val) if val == "C:\\Games\\Game"));
+        assert!(matches!((&values["Name"], &name), (RegValue::Str(val), RegData::Str(expected)) if val == expected));
+        assert!(matches!(entries.keys["HKLM"][&format!("{}\\1.0", KEY)]["Language"], RegValue::UInt(0x13)));
+    }
+}
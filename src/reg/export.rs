@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fs::{File, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::common::{Installation, app_data_dir};
+use super::{Regentries, RegData, regentries, installation_template_data};
+
+const REG_FILE_HEADER: &str = "Windows Registry Editor Version 5.00";
+
+fn hive_name(hive: &str) -> &str {
+    match hive {
+        "HKLM" => "HKEY_LOCAL_MACHINE",
+        "HKCU" => "HKEY_CURRENT_USER",
+        other => other
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+pub fn format_value(name: &str, data: &RegData) -> String {
+    let name = match name.is_empty() {
+        true => String::from("@"),
+        false => format!("\"{}\"", escape(name))
+    };
+    match data {
+        RegData::Str(val) => format!("{}=\"{}\"", name, escape(val)),
+        RegData::UInt(val) => format!("{}=dword:{:08x}", name, val)
+    }
+}
+
+/// Formats the given keys and values (hive name, key) -> values as the contents of a .reg file
+pub fn format_reg_file(keys: &BTreeMap<(String, String), Vec<(String, RegData)>>) -> String {
+    std::iter::once(String::from(REG_FILE_HEADER))
+        .chain(keys.iter().map(|((hive, key), values)| {
+            std::iter::once(format!("\r\n[{}\\{}]", hive_name(hive), key))
+                .chain(values.iter().map(|(name, data)| format_value(name, data)))
+                .collect::<Vec<String>>()
+                .join("\r\n")
+        }))
+        .collect::<Vec<String>>()
+        .join("\r\n") + "\r\n"
+}
+
+/// Renders the registry entries with the given template data into the contents of a .reg file
+pub fn render_reg_file(entries: &Regentries, data: &BTreeMap<&str, String>) -> Result<String, String> {
+    let mut keys = BTreeMap::new();
+    for (hive, hive_keys) in entries.keys.iter() {
+        for (key, values) in hive_keys.iter() {
+            let rendered = values.iter()
+                .map(|(name, value)| value.render(data).map(|d| (name.to_string(), d)))
+                .collect::<Result<Vec<(String, RegData)>, String>>()?;
+            keys.insert((hive.clone(), key.clone()), rendered);
+        }
+    }
+    Ok(format_reg_file(&keys))
+}
+
+/// Writes a .reg file. regedit expects "Version 5.00" files to be UTF-16LE encoded (with BOM).
+pub fn write_reg_file(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let bytes = std::iter::once(0xFEFFu16)
+        .chain(contents.encode_utf16())
+        .flat_map(|c| c.to_le_bytes())
+        .collect::<Vec<u8>>();
+    File::create(path)
+        .and_then(|mut f| f.write_all(&bytes))
+        .map_err(|e| e.to_string())
+}
+
+/// Exports the registry entries for the installation to %APPDATA%\BFME2 Installer\registry,
+/// so they can be imported manually (e.g. without admin rights or on another machine)
+pub fn export_installation(installation: &Installation) -> Result<PathBuf, String> {
+    let data = installation_template_data(installation);
+    let contents = render_reg_file(&regentries(&installation.game)?, &data)?;
+
+    let target = app_data_dir().join("registry")
+        .join(format!("{}_{}.reg", installation.game.to_string().to_lowercase(), installation.checksum));
+    println!("Exporting registry entries to {:?}...", target);
+    write_reg_file(&target, &contents)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::common::Game;
+    use super::*;
+
+    fn read_reg_file(path: &Path) -> String {
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(&bytes[..2], &[0xFF, 0xFE]);
+        String::from_utf16(&bytes[2..].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<u16>>()).unwrap()
+    }
+
+    #[test]
+    fn exported_installation_parses_as_its_definitions() {
+        let mut installation = Installation::defaults(Game::from_id("ROTWK").unwrap());
+        installation.path = String::from("C:\\Spiele\\Schlacht um Mittelerde™ II – \"Aufstieg\"");
+        installation.checksum = String::from("0123456789abcdef0123456789abcdef");
+        installation.ergc = String::from("ABCD1234EFGH5678IJKL");
+        let path = export_installation(&installation).unwrap();
+        let contents = read_reg_file(&path);
+        assert!(contents.starts_with(REG_FILE_HEADER));
+        assert!(contents.contains("\r\n"));
+
+        let data = installation_template_data(&installation);
+        let expected = regentries(&installation.game).unwrap();
+        let exported = Regentries::parse(&contents).unwrap();
+        assert_eq!(exported.keys.keys().collect::<Vec<_>>(), expected.keys.keys().collect::<Vec<_>>());
+        for (hive, keys) in expected.keys.iter() {
+            assert_eq!(exported.keys[hive].keys().collect::<Vec<_>>(), keys.keys().collect::<Vec<_>>());
+            for (key, values) in keys.iter() {
+                assert_eq!(exported.keys[hive][key].len(), values.len());
+                for (name, value) in values.iter() {
+                    assert_eq!(exported.keys[hive][key][name].render(&data).unwrap(), value.render(&data).unwrap(),
+                               "{}\\{}\\{}", hive, key, name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn escaped_values_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let values = vec![
+            (String::new(), RegData::Str(String::from("default"))),
+            (String::from("Name \"quoted\""), RegData::Str(String::from("C:\\Games\\\"Ära\"\\"))),
+            (String::from("Count"), RegData::UInt(0xdeadbeef))
+        ];
+        let mut keys = BTreeMap::new();
+        keys.insert((String::from("HKCU"), String::from("Software\\Game")), values.clone());
+        let path = dir.path().join("sub").join("test.reg");
+        write_reg_file(&path, &format_reg_file(&keys)).unwrap();
+
+        let parsed = Regentries::parse(&read_reg_file(&path)).unwrap();
+        let parsed = &parsed.keys["HKCU"]["Software\\Game"];
+        assert_eq!(parsed.len(), values.len());
+        for (name, data) in values {
+            assert_eq!(parsed[&name].render(&BTreeMap::new()).unwrap(), data);
+        }
+    }
+}
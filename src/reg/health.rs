@@ -0,0 +1,156 @@
+use crate::common::Installation;
+use super::{RegData, Hive, regentries, installation_template_data};
+use super::backend::registry;
+use super::elevation::registered_entries;
+
+/// A registry value which differs from what the registry definitions render for the installation
+#[derive(Debug, Clone)]
+pub struct Drift {
+    pub hive: String,
+    pub key: String,
+    pub name: String,
+    pub expected: RegData,
+    /// None if the value is missing (or can't be read)
+    pub actual: Option<RegData>
+}
+
+impl Drift {
+    pub fn describe(&self) -> String {
+        let name = match self.name.is_empty() {
+            true => "(Default)",
+            false => &self.name
+        };
+        match &self.actual {
+            Some(actual) => format!("{}\\{}: {} is {}, expected {}", self.hive, self.key, name,
+                                    format_data(actual), format_data(&self.expected)),
+            None => format!("{}\\{}: {} is missing, expected {}", self.hive, self.key, name,
+                            format_data(&self.expected))
+        }
+    }
+}
+
+fn format_data(data: &RegData) -> String {
+    match data {
+        RegData::Str(val) => format!("\"{}\"", val),
+        RegData::UInt(val) => format!("0x{:08x}", val)
+    }
+}
+
+/// Compares the live registry values with the values rendered from the registry definitions. Installations
+/// registered without admin rights are compared with their VirtualStore values.
+pub fn check(installation: &Installation) -> Result<Vec<Drift>, String> {
+    let registry = registry();
+    let data = installation_template_data(installation);
+    let mut drifts = vec![];
+
+    for (hive, keys) in registered_entries(&regentries(&installation.game)?, installation.registration_mode)?.keys.iter() {
+        for (key, values) in keys.iter() {
+            for (name, value) in values.iter() {
+                let expected = value.render(&data)?;
+                let actual = match registry.get_value(Hive::from_name(hive)?, key, name) {
+                    Ok(actual) => Some(actual),
+                    Err(e) => {
+                        println!("Could not read {}\\{}\\{}: {}", hive, key, name, e);
+                        None
+                    }
+                };
+                if actual.as_ref() != Some(&expected) {
+                    drifts.push(Drift {
+                        hive: hive.clone(),
+                        key: key.clone(),
+                        name: name.clone(),
+                        expected,
+                        actual
+                    });
+                }
+            }
+        }
+    }
+    Ok(drifts)
+}
+
+/// Writes the expected values back to the registry
+pub fn repair(drifts: &Vec<Drift>) -> Result<(), String> {
+    let registry = registry();
+    for drift in drifts {
+        println!("Repair {}", drift.describe());
+        registry.set_value(Hive::from_name(&drift.hive)?, &drift.key, &drift.name, &drift.expected)
+            .map_err(|e| format!("Could not write {}\\{}\\{}: {}", drift.hive, drift.key, drift.name, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::Game;
+    use crate::reg::backend::{RegistryBackend, test_registry};
+    use crate::reg::elevation::{RegistrationMode, virtual_store_key};
+    use super::*;
+
+    fn installation(mode: RegistrationMode) -> Installation {
+        let mut installation = Installation::defaults(Game::from_id("BFME2").unwrap());
+        installation.path = String::from("C:\\Games\\BFME2");
+        installation.checksum = String::from("0123456789abcdef0123456789abcdef");
+        installation.ergc = String::from("ABCD1234EFGH5678IJKL");
+        installation.registration_mode = mode;
+        installation
+    }
+
+    /// Writes the values the installation expects, as the installer would
+    fn register(installation: &Installation) {
+        let data = installation_template_data(installation);
+        for (hive, keys) in registered_entries(&regentries(&installation.game).unwrap(), installation.registration_mode).unwrap().keys.iter() {
+            for (key, values) in keys.iter() {
+                for (name, value) in values.iter() {
+                    test_registry().set_value(Hive::from_name(hive).unwrap(), key, name, &value.render(&data).unwrap()).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn registered_installation_has_no_drifts() {
+        let installation = installation(RegistrationMode::Admin);
+        assert!(!check(&installation).unwrap().is_empty());
+        register(&installation);
+        assert!(check(&installation).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_and_wrong_values_are_repaired() {
+        let installation = installation(RegistrationMode::Admin);
+        register(&installation);
+        let install_path = &installation.game.definition().registry.install_path;
+        let ergc = &installation.game.definition().registry.ergc;
+        test_registry().delete_value(Hive::from_name(&install_path.hive).unwrap(), &install_path.key, &install_path.name).unwrap();
+        test_registry().set_value(Hive::from_name(&ergc.hive).unwrap(), &ergc.key, &ergc.name, &RegData::Str(String::from("WRONG"))).unwrap();
+
+        let drifts = check(&installation).unwrap();
+        assert_eq!(drifts.len(), 2);
+        let missing = drifts.iter().find(|drift| drift.actual.is_none()).unwrap();
+        assert_eq!((missing.key.as_str(), missing.name.as_str()), (install_path.key.as_str(), install_path.name.as_str()));
+        let wrong = drifts.iter().find(|drift| drift.actual.is_some()).unwrap();
+        assert_eq!(wrong.actual, Some(RegData::Str(String::from("WRONG"))));
+        assert_eq!(wrong.expected, RegData::Str(installation.ergc.clone()));
+
+        repair(&drifts).unwrap();
+        assert!(check(&installation).unwrap().is_empty());
+    }
+
+    #[test]
+    fn user_mode_checks_the_virtual_store() {
+        let installation = installation(RegistrationMode::User);
+        register(&installation);
+        assert!(check(&installation).unwrap().is_empty());
+
+        let install_path = &installation.game.definition().registry.install_path;
+        let (_, virtual_key) = virtual_store_key(Hive::from_name(&install_path.hive).unwrap(), &install_path.key).unwrap();
+        test_registry().delete_value(Hive::CurrentUser, &virtual_key, &install_path.name).unwrap();
+        let drifts = check(&installation).unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert_eq!((drifts[0].hive.as_str(), drifts[0].key.as_str()), ("HKCU", virtual_key.as_str()));
+        repair(&drifts).unwrap();
+        assert!(test_registry().get_value(Hive::LocalMachine, &install_path.key, &install_path.name).is_err());
+        assert!(check(&installation).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,18 @@
-use phf::phf_map;
-use winreg;
-use winreg::enums::HKEY_LOCAL_MACHINE;
-use winreg::types::FromRegValue;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::common::{Game, Installation, Language};
+use crate::template;
+use backend::{FromRegData, registry};
+pub use backend::{Hive, RegData};
+
+pub mod backend;
+pub mod backup;
+pub mod definitions;
+pub mod elevation;
+pub mod export;
+pub mod health;
+#[cfg(not(windows))]
+pub mod wine;
 
 macro_rules! hashmap {
     ($( $key: expr => $val: expr ),*) => {{
@@ -11,151 +22,72 @@ macro_rules! hashmap {
     }}
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Registry entries of a game: hive ("HKLM"/"HKCU") -> key -> value name -> value
+#[derive(Debug, Clone)]
 pub struct Regentries {
-    pub(crate) keys: &'static phf::Map<&'static str, &'static phf::Map<&'static str, &'static RegEntryMap>>
+    pub(crate) keys: BTreeMap<String, BTreeMap<String, RegEntryMap>>
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub(crate) enum RegValue {
-    Str(&'static str),
-    Template(&'static str),
-    UInt(u32)
+    Str(String),
+    Template(String),
+    UInt(u32),
+    /// DWORD template, rendering to a hex number like `dword:` values in .reg files
+    UIntTemplate(String)
 }
 
-// impl RegValue {
-//     fn from_string(s: &str) -> RegValue {
-//         RegValue::Str(s.parse().unwrap())
-//     }
-// }
-
-type RegEntryMap = phf::Map<&'static str, RegValue>;
-
-static ERGC: RegEntryMap = phf_map! {
-    "" => RegValue::Template("{{ ergc }}")
-};
-
-static BFME2_0: RegEntryMap = phf_map! {
-    "Language" => RegValue::Str("english"),
-    "InstallPath" => RegValue::Template("{{ install_path }}"),
-    "MapPackVersion" => RegValue::UInt(00010000),
-    "UseLocalUserMaps" => RegValue::UInt(00000000),
-    "UserDataLeafName" => RegValue::Template("bfme2_{{ checksum }}"),
-    "Version" => RegValue::UInt(00010000),
-};
-
-static BFME2_1: RegEntryMap = phf_map! {
-    "DisplayName" => RegValue::Str("The Battle for Middle-earth (tm) II"),
-    "Language" => RegValue::UInt(00000013),
-    "LanguageName" => RegValue::Str("English UK")
-};
-
-static BFME2_2: RegEntryMap = phf_map! {
-    "CacheSize" => RegValue::Str("5499066368"),
-    "CD Drive" => RegValue::Str("D:\\"),
-    "DisplayName" => RegValue::Str("The Battle for Middle-earth (tm) II"),
-    "Folder" => RegValue::Str("C:\\ProgramData\\Microsoft\\Windows\\Start Menu\\Programs\\Electronic Arts\\BFME2\\"),
-    "Install Dir" => RegValue::Template("{{ install_path_shorthand }}"),
-    "Installed From" => RegValue::Str("D:\\"),
-    "Language" => RegValue::Str("English UK"),
-    "Locale" => RegValue::Str("en_uk"),
-    "Patch URL" => RegValue::Str("http://transtest.ea.com/Electronic Arts/The Battle for Middle-earth 2/NorthAmerica"),
-    "Product GUID" => RegValue::Str("{2A9F95AB-65A3-432c-8631-B8BC5BF7477A}"),
-    "Region" => RegValue::Str("NorthAmerica"),
-    "Registration" => RegValue::Str("SOFTWARE\\Electronic Arts\\Electronic Arts\\The Battle for Middle-earth II\\ergc"),
-    "Suppression Exe" => RegValue::Str("rtsi.exe"),
-    "SwapSize" => RegValue::Str("0")
-};
-
-static BFME2_3: RegEntryMap = phf_map! {
-    "Game Registry" => RegValue::Str("SOFTWARE\\Electronic Arts\\The Battle for Middle-earth II"),
-    "Restart" => RegValue::UInt(00000000),
-    "DirectX Installed" => RegValue::UInt(00000000),
-    "Installed" => RegValue::UInt(00000001),
-    "" => RegValue::Template("{{ install_path }}\\lotrbfme2.exe"),
-    "Path" => RegValue::Template("{{ install_path }}")
-};
-
-static BFME2_4: RegEntryMap = phf_map! {
-    "checksum" => RegValue::Template("{{ checksum }}")
-};
-
-static BFME2_INNER: &phf::Map<&'static str, &'static RegEntryMap> = &phf_map! {
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\The Battle for Middle-earth II" => &BFME2_0,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\The Battle for Middle-earth II\\ergc" => &ERGC,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\The Battle for Middle-earth II\\1.0" => &BFME2_1,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\The Battle for Middle-earth II" => &BFME2_2,
-    "SOFTWARE\\WOW6432Node\\Microsoft\\Windows\\CurrentVersion\\App Paths\\lotrbfme2.exe" => &BFME2_3,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\BFME2 Installer\\BFME2" => &BFME2_4
-};
-
-static BFME2_KEYS: &phf::Map<&'static str, &phf::Map<&'static str, &'static RegEntryMap>> = &phf_map! {
-    "HKLM" => BFME2_INNER
-};
-
-pub static BFME2: Regentries = Regentries {
-    keys: BFME2_KEYS
-};
-
-static ROTWK_0: RegEntryMap = phf_map! {
-    "Language" => RegValue::Str("english"),
-    "InstallPath" => RegValue::Template("{{ install_path }}"),
-    "MapPackVersion" => RegValue::UInt(00020000),
-    "UseLocalUserMaps" => RegValue::UInt(00000000),
-    "UserDataLeafName" => RegValue::Template("rotwk_{{ checksum }}"),
-    "Version" => RegValue::UInt(00020000)
-};
-
-static ROTWK_1: RegEntryMap = phf_map! {
-    "DisplayName" => RegValue::Str("The Lord of the Rings, The Rise of the Witch-king"),
-    "Language" => RegValue::UInt(00000013),
-    "LanguageName" => RegValue::Str("English UK")
-};
-
-static ROTWK_2: RegEntryMap = phf_map! {
-    "CacheSize" => RegValue::Str("3139187712"),
-    "CD Drive" => RegValue::Str("D:\\"),
-    "DisplayName" => RegValue::Str("The Lord of the Rings, The Rise of the Witch-king"),
-    "Folder" => RegValue::Str("C:\\ProgramData\\Microsoft\\Windows\\Start Menu\\Programs\\Electronic Arts\\ROTWK\\"),
-    "Install Dir" => RegValue::Template("{{ install_path_shorthand }}"),
-    "Installed From" => RegValue::Str("D:\\"),
-    "Language" => RegValue::Str("English UK"),
-    "Locale" => RegValue::Str("en_uk"),
-    "Patch URL" => RegValue::Str("http://transtest.ea.com/Electronic Arts/The Battle for Middle-earth 2/NorthAmerica"),
-    "Product GUID" => RegValue::Str("{B931FB80-537A-4600-00AD-AC5DEDB6C25B}"),
-    "Region" => RegValue::Str("NorthAmerica"),
-    "Registration" => RegValue::Str("SOFTWARE\\Electronic Arts\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king\\ergc"),
-    "Suppression Exe" => RegValue::Str("rtsi.exe"),
-    "SwapSize" => RegValue::Str("0")
-};
-
-static ROTWK_3: RegEntryMap = phf_map! {
-    "checksum" => RegValue::Template("{{ checksum }}")
-};
+impl RegValue {
+    pub fn render(&self, data: &BTreeMap<&str, String>) -> Result<RegData, String> {
+        match self {
+            RegValue::Str(val) => Ok(RegData::Str(val.clone())),
+            RegValue::UInt(val) => Ok(RegData::UInt(*val)),
+            RegValue::Template(tmpl) => template::render(tmpl, data).map(RegData::Str),
+            RegValue::UIntTemplate(tmpl) => {
+                let rendered = template::render(tmpl, data)?;
+                u32::from_str_radix(rendered.trim(), 16)
+                    .map(RegData::UInt)
+                    .map_err(|e| format!("Invalid DWORD '{}' rendered from '{}': {}", rendered, tmpl, e))
+            }
+        }
+    }
+}
 
-static ROTWK_INNER: &phf::Map<&'static str, &'static RegEntryMap> = &phf_map! {
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king" => &ROTWK_0,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king\\ergc" => &ERGC,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king\\1.0" => &ROTWK_1,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\The Lord of the Rings, The Rise of the Witch-king" => &ROTWK_2,
-    "SOFTWARE\\WOW6432Node\\Electronic Arts\\BFME2 Installer\\ROTWK" => &ROTWK_3
-};
+type RegEntryMap = BTreeMap<String, RegValue>;
 
-static ROTWK_KEYS: &phf::Map<&'static str, &phf::Map<&'static str, &'static RegEntryMap>> = &phf_map! {
-    "HKLM" => ROTWK_INNER
-};
+pub fn regentries(game: &Game) -> Result<Regentries, String> {
+    definitions::load(game)
+}
 
-pub static ROTWK: Regentries = Regentries {
-    keys: ROTWK_KEYS
-};
+/// Data available to the registry value templates. `install_path` has to be the path as seen by the game.
+pub fn template_data(game: &Game, install_path: &str, ergc: &str, checksum: &str, sync_fingerprint: &str, language: &Language) -> BTreeMap<&'static str, String> {
+    let mut reg_data = BTreeMap::new();
+    reg_data.insert("executable", game.definition().executable.clone());
+    reg_data.insert("userdata_leaf_name", game.userdata_leaf_name(checksum));
+    reg_data.insert("install_path", String::from(install_path));
+    reg_data.insert("install_path_shorthand", String::from(install_path));
+    reg_data.insert("ergc", String::from(ergc));
+    reg_data.insert("checksum", String::from(checksum));
+    reg_data.insert("sync_fingerprint", String::from(sync_fingerprint));
+    reg_data.insert("language", String::from(language.name()));
+    reg_data.insert("language_name", String::from(language.display_name()));
+    reg_data.insert("locale", String::from(language.locale()));
+    reg_data.insert("language_id", format!("{:08x}", language.id()));
+    reg_data
+}
 
+/// Template data for an existing installation
+pub fn installation_template_data(installation: &Installation) -> BTreeMap<&'static str, String> {
+    let install_path = PathBuf::from(&installation.path);
+    let install_path = registry().to_registry_path(&install_path.canonicalize().unwrap_or(install_path));
+    let mut data = template_data(&installation.game, &install_path, &installation.ergc, &installation.checksum,
+                                 &installation.sync_fingerprint, &installation.language);
+    // Named profiles have their own userdata directory
+    data.insert("userdata_leaf_name", installation.userdata_leaf_name());
+    data
+}
 
-pub fn get_reg_value<T: FromRegValue>(root: winreg::HKEY, path: &str, name: &str) -> iced::futures::io::Result<T> {
-    let hklm = winreg::RegKey::predef(root);
-    match hklm.open_subkey(path) {
-        Ok(reg_key) => {
-            reg_key.get_value(name)
-        },
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+pub fn get_reg_value<T: FromRegData>(root: Hive, path: &str, name: &str) -> std::io::Result<T> {
+    registry().get_value(root, path, name)
+        .and_then(T::from_reg_data)
+}
@@ -0,0 +1,467 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::backend::{Hive, RegData, RegistryBackend};
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+/// Key in user.reg with the paths of the user's special folders
+const SHELL_FOLDERS_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\Shell Folders";
+
+/// Registry backend operating on the text based registry files of a Wine prefix
+/// (system.reg for HKLM, user.reg for HKCU).
+///
+/// wineserver keeps the registry in memory while running and overwrites the files when it exits,
+/// so it must not be running while the installer writes to the prefix (see `wineserver -k`).
+#[derive(Debug)]
+pub struct WineRegistry {
+    prefix: PathBuf,
+    lock: Mutex<()>
+}
+
+#[derive(Debug, Clone)]
+struct WineKey {
+    path: String,
+    header: String,
+    lines: Vec<String>
+}
+
+#[derive(Debug, Clone)]
+struct WineRegFile {
+    preamble: Vec<String>,
+    keys: Vec<WineKey>
+}
+
+impl WineRegistry {
+    pub fn new(prefix: PathBuf) -> WineRegistry {
+        WineRegistry { prefix, lock: Mutex::new(()) }
+    }
+
+    /// The prefix from $WINEPREFIX or ~/.wine, if it contains a registry
+    pub fn default_prefix() -> Option<PathBuf> {
+        std::env::var_os("WINEPREFIX")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".wine")))
+            .filter(|prefix| prefix.join("system.reg").exists())
+    }
+
+    fn file_path(&self, hive: Hive) -> PathBuf {
+        self.prefix.join(match hive {
+            Hive::LocalMachine => "system.reg",
+            Hive::CurrentUser => "user.reg"
+        })
+    }
+
+    fn read_file(&self, hive: Hive) -> io::Result<WineRegFile> {
+        Ok(WineRegFile::parse(&fs::read_to_string(self.file_path(hive))?))
+    }
+
+    /// The first user directory of the prefix with an AppData folder, or else the one of the current user
+    fn guess_appdata_path(&self) -> PathBuf {
+        let users_dir = self.prefix.join("drive_c").join("users");
+        let mut users = fs::read_dir(&users_dir)
+            .map(|entries| entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|path| !path.ends_with("Public"))
+                .collect::<Vec<PathBuf>>())
+            .unwrap_or_default();
+        users.sort();
+        users.iter()
+            .flat_map(|user| vec![user.join("AppData").join("Roaming"), user.join("Application Data")])
+            .find(|path| path.is_dir())
+            .unwrap_or(users_dir.join(std::env::var("USER").unwrap_or(String::from("user")))
+                .join("AppData").join("Roaming"))
+    }
+
+    /// 32 bit programs (like the game) see HKLM\SOFTWARE\WOW6432Node as HKLM\SOFTWARE in 64 bit prefixes.
+    /// In 32 bit prefixes there is no WOW6432Node and the keys live directly under SOFTWARE.
+    fn map_path(path: &str, file: &WineRegFile) -> String {
+        let is_win32 = file.preamble.iter().any(|line| line.trim() == "#arch=win32");
+        if is_win32 {
+            path.split('\\')
+                .filter(|component| component.to_lowercase() != "wow6432node")
+                .collect::<Vec<&str>>()
+                .join("\\")
+        } else {
+            path.to_string()
+        }
+    }
+}
+
+impl RegistryBackend for WineRegistry {
+    fn get_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<RegData> {
+        let _guard = self.lock.lock().unwrap();
+        let file = self.read_file(hive)?;
+        let path = Self::map_path(path, &file);
+        let key = file.find_key(&path)
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Key not found: {}", path)))?;
+        key.lines.iter()
+            .filter_map(|line| parse_value_line(line))
+            .find(|(value_name, _)| value_name.to_lowercase() == name.to_lowercase())
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Value not found: {}\\{}", path, name)))?
+            .1
+    }
+
+    fn set_value(&self, hive: Hive, path: &str, name: &str, value: &RegData) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.read_file(hive)?;
+        let path = Self::map_path(path, &file);
+        let value_line = format_value_line(name, value);
+
+        let key = file.find_or_create_key(&path);
+        match key.lines.iter().position(|line| parse_value_line(line)
+            .map(|(value_name, _)| value_name.to_lowercase() == name.to_lowercase())
+            .unwrap_or(false)) {
+            Some(i) => key.lines[i] = value_line,
+            None => key.lines.push(value_line)
+        };
+        fs::write(self.file_path(hive), file.to_string())
+    }
+
+    fn delete_value(&self, hive: Hive, path: &str, name: &str) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.read_file(hive)?;
+        let path = Self::map_path(path, &file);
+        let key = file.find_key_mut(&path)
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Key not found: {}", path)))?;
+        let i = key.lines.iter().position(|line| parse_value_line(line)
+            .map(|(value_name, _)| value_name.to_lowercase() == name.to_lowercase())
+            .unwrap_or(false))
+            .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("Value not found: {}\\{}", path, name)))?;
+        key.lines.remove(i);
+        fs::write(self.file_path(hive), file.to_string())
+    }
+
+    fn to_registry_path(&self, path: &Path) -> String {
+        let drive_c = self.prefix.join("drive_c");
+        match path.strip_prefix(&drive_c) {
+            Ok(rel_path) => format!("C:\\{}", rel_path.to_string_lossy().replace("/", "\\")),
+            // Wine maps Z: to the file system root by default
+            Err(_) => format!("Z:{}", path.to_string_lossy().replace("/", "\\"))
+        }
+    }
+
+    fn from_registry_path(&self, path: &str) -> PathBuf {
+        let path = path.replace("\\", "/");
+        match path.split_at(2.min(path.len())) {
+            (drive, rest) if drive.eq_ignore_ascii_case("c:") =>
+                self.prefix.join("drive_c").join(rest.trim_start_matches('/')),
+            (drive, rest) if drive.eq_ignore_ascii_case("z:") => PathBuf::from(rest),
+            _ => PathBuf::from(path)
+        }
+    }
+
+    /// The AppData folder of the prefix's user as registered in user.reg. Older prefixes use
+    /// `Application Data` instead of `AppData\Roaming`, and the user name is the one of whoever created the prefix.
+    fn appdata_path(&self) -> PathBuf {
+        match self.get_value(Hive::CurrentUser, SHELL_FOLDERS_KEY, "AppData") {
+            Ok(RegData::Str(path)) => self.from_registry_path(&path),
+            _ => {
+                println!("WARNING: No AppData folder in {:?}, guessing it", self.file_path(Hive::CurrentUser));
+                self.guess_appdata_path()
+            }
+        }
+    }
+}
+
+impl WineRegFile {
+    fn parse(contents: &str) -> WineRegFile {
+        let mut file = WineRegFile { preamble: vec![], keys: vec![] };
+        let mut continued = false;
+
+        for line in contents.replace("\r", "").split('\n') {
+            if line.starts_with('[') && !continued {
+                let (path, header) = match line.rfind(']') {
+                    Some(i) => (&line[1..i], &line[i + 1..]),
+                    None => (&line[1..], "")
+                };
+                file.keys.push(WineKey {
+                    path: unescape(path),
+                    header: header.to_string(),
+                    lines: vec![]
+                });
+                continue;
+            }
+            // Values (e.g. hex data) can be continued on the next line with a trailing backslash
+            let lines = match file.keys.last_mut() {
+                Some(key) => &mut key.lines,
+                None => &mut file.preamble
+            };
+            if continued {
+                let last = lines.last_mut().unwrap();
+                last.push('\n');
+                last.push_str(line);
+            } else {
+                lines.push(line.to_string());
+            }
+            continued = line.ends_with('\\') && !line.ends_with("\\\\");
+        }
+        file
+    }
+
+    fn find_key(&self, path: &str) -> Option<&WineKey> {
+        self.keys.iter().find(|key| key.path.to_lowercase() == path.to_lowercase())
+    }
+
+    fn find_key_mut(&mut self, path: &str) -> Option<&mut WineKey> {
+        self.keys.iter_mut().find(|key| key.path.to_lowercase() == path.to_lowercase())
+    }
+
+    fn find_or_create_key(&mut self, path: &str) -> &mut WineKey {
+        match self.keys.iter().position(|key| key.path.to_lowercase() == path.to_lowercase()) {
+            Some(i) => &mut self.keys[i],
+            None => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                let filetime = (now.as_secs() + FILETIME_UNIX_OFFSET) * 10_000_000
+                    + now.subsec_nanos() as u64 / 100;
+                self.keys.push(WineKey {
+                    path: path.to_string(),
+                    header: format!(" {}", now.as_secs()),
+                    lines: vec![format!("#time={:x}", filetime)]
+                });
+                self.keys.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn to_string(&self) -> String {
+        let mut lines = self.preamble.clone();
+        for key in &self.keys {
+            if lines.last().map(|l| !l.is_empty()).unwrap_or(false) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]{}", escape(&key.path), key.header));
+            lines.extend(key.lines.iter().cloned());
+        }
+        let mut contents = lines.join("\n");
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents
+    }
+}
+
+/// Parses lines like `"Name"="value"`, `"Name"=dword:00000001` or `@="default value"`
+fn parse_value_line(line: &str) -> Option<(String, io::Result<RegData>)> {
+    let (name, data) = if line.starts_with('@') {
+        (String::new(), line[1..].strip_prefix('=')?)
+    } else if line.starts_with('"') {
+        let end = find_closing_quote(line, 1)?;
+        (unescape(&line[1..end]), line[end + 1..].strip_prefix('=')?)
+    } else {
+        return None
+    };
+
+    let value = if data.starts_with('"') {
+        find_closing_quote(data, 1)
+            .map(|end| RegData::Str(unescape(&data[1..end])))
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid string value: {}", data)))
+    } else if let Some(expand_str) = data.strip_prefix("str(2):\"") {
+        find_closing_quote(expand_str, 0)
+            .map(|end| RegData::Str(unescape(&expand_str[..end])))
+            .ok_or(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid string value: {}", data)))
+    } else if let Some(dword) = data.strip_prefix("dword:") {
+        u32::from_str_radix(dword.trim(), 16)
+            .map(RegData::UInt)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported value type: {}", data)))
+    };
+    Some((name, value))
+}
+
+fn format_value_line(name: &str, value: &RegData) -> String {
+    let name = match name.is_empty() {
+        true => String::from("@"),
+        false => format!("\"{}\"", escape(name))
+    };
+    match value {
+        RegData::Str(val) => format!("{}=\"{}\"", name, escape(val)),
+        RegData::UInt(val) => format!("{}=dword:{:08x}", name, val)
+    }
+}
+
+fn find_closing_quote(s: &str, start: usize) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip_while(|(i, _)| *i < start) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false
+        }
+    }
+    None
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
+            Some('x') => {
+                let mut hex = String::new();
+                while hex.len() < 4 && chars.peek().map(|c| c.is_ascii_hexdigit()).unwrap_or(false) {
+                    hex.push(chars.next().unwrap());
+                }
+                if let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32) {
+                    result.push(c);
+                }
+            },
+            Some(other) => result.push(other),
+            None => result.push('\\')
+        }
+    }
+    result
+}
+
+fn escape(s: &str) -> String {
+    s.chars().map(|c| match c {
+        '\\' => String::from("\\\\"),
+        '"' => String::from("\\\""),
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        '\t' => String::from("\\t"),
+        c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+        c => format!("\\x{:04x}", c as u32)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use super::*;
+
+    const SYSTEM_REG: &str = "WINE REGISTRY Version 2
+;; All keys relative to \\\\Machine
+
+#arch=win64
+
+[Software\\\\WOW6432Node\\\\Electronic Arts\\\\Electronic Arts\\\\The Battle for Middle-earth II] 1700000000
+#time=1d9a1b2c3d4e5f6
+\"CacheSize\"=\"8034922496\"
+\"InstallPath\"=\"C:\\\\Program Files (x86)\\\\Electronic Arts\\\\The Battle for Middle-earth (tm) II\\\\\"
+\"Language\"=dword:00000013
+@=\"default\"
+
+[Software\\\\Wine\\\\Binary] 1700000000
+\"Data\"=hex:00,01,02,03,04,05,06,07,08,09,0a,0b,0c,0d,0e,0f,10,11,12,13,14,15,16,17,\\
+  18,19,1a,1b
+\"After\"=\"continued\"
+";
+
+    fn prefix(system_reg: &str, user_reg: &str) -> (TempDir, WineRegistry) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("system.reg"), system_reg).unwrap();
+        fs::write(dir.path().join("user.reg"), user_reg).unwrap();
+        let registry = WineRegistry::new(dir.path().to_path_buf());
+        (dir, registry)
+    }
+
+    #[test]
+    fn parse_and_write_back_unchanged() {
+        assert_eq!(WineRegFile::parse(SYSTEM_REG).to_string(), SYSTEM_REG);
+    }
+
+    #[test]
+    fn read_values() {
+        let (_dir, registry) = prefix(SYSTEM_REG, "WINE REGISTRY Version 2\n");
+        let key = "SOFTWARE\\WOW6432Node\\Electronic Arts\\Electronic Arts\\The Battle for Middle-earth II";
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "installpath").unwrap(),
+                   RegData::Str(String::from("C:\\Program Files (x86)\\Electronic Arts\\The Battle for Middle-earth (tm) II\\")));
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "Language").unwrap(), RegData::UInt(0x13));
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "").unwrap(), RegData::Str(String::from("default")));
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "Missing").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(registry.get_value(Hive::LocalMachine, "Software\\Wine\\Binary", "After").unwrap(),
+                   RegData::Str(String::from("continued")));
+        assert_eq!(registry.get_value(Hive::LocalMachine, "Software\\Wine\\Binary", "Data").unwrap_err().kind(),
+                   io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn write_and_delete_values() {
+        let (_dir, registry) = prefix(SYSTEM_REG, "WINE REGISTRY Version 2\n");
+        let key = "SOFTWARE\\WOW6432Node\\Electronic Arts\\BFME2 Installer\\BFME2";
+        let path = String::from("Z:\\home\\me\\Tom's \"Games\" & more\\BFME2");
+        registry.set_value(Hive::LocalMachine, key, "install_path", &RegData::Str(path.clone())).unwrap();
+        registry.set_value(Hive::LocalMachine, key, "count", &RegData::UInt(7)).unwrap();
+        registry.set_value(Hive::LocalMachine, key, "count", &RegData::UInt(8)).unwrap();
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "install_path").unwrap(), RegData::Str(path));
+        assert_eq!(registry.get_value(Hive::LocalMachine, key, "count").unwrap(), RegData::UInt(8));
+        // Other keys are written back unchanged
+        assert!(fs::read_to_string(registry.file_path(Hive::LocalMachine)).unwrap().starts_with(&SYSTEM_REG[..SYSTEM_REG.find("[Software\\\\Wine").unwrap()]));
+
+        registry.delete_value(Hive::LocalMachine, key, "count").unwrap();
+        assert!(registry.get_value(Hive::LocalMachine, key, "count").is_err());
+        registry.delete_value(Hive::LocalMachine, key, "install_path").unwrap();
+        assert!(registry.get_value(Hive::LocalMachine, key, "install_path").is_err());
+        assert!(registry.get_value(Hive::LocalMachine, "Software\\Wine\\Binary", "After").is_ok());
+    }
+
+    #[test]
+    fn win32_prefixes_have_no_wow6432node() {
+        let (_dir, registry) = prefix("WINE REGISTRY Version 2\n\n#arch=win32\n", "WINE REGISTRY Version 2\n");
+        registry.set_value(Hive::LocalMachine, "SOFTWARE\\WOW6432Node\\Electronic Arts\\Test", "a", &RegData::UInt(1)).unwrap();
+        assert_eq!(registry.get_value(Hive::LocalMachine, "SOFTWARE\\Electronic Arts\\Test", "a").unwrap(), RegData::UInt(1));
+        assert!(fs::read_to_string(registry.file_path(Hive::LocalMachine)).unwrap().contains("[SOFTWARE\\\\Electronic Arts\\\\Test]"));
+    }
+
+    #[test]
+    fn escaping() {
+        for value in ["plain", "C:\\Games\\", "say \"hi\"", "tab\there\nnew line", "Français", "\u{2603}"] {
+            assert_eq!(unescape(&escape(value)), value);
+            let line = format_value_line("Name \"quoted\"", &RegData::Str(value.to_string()));
+            let (name, data) = parse_value_line(&line).unwrap();
+            assert_eq!(name, "Name \"quoted\"");
+            assert_eq!(data.unwrap(), RegData::Str(value.to_string()));
+        }
+        assert_eq!(escape("C:\\a\"b"), "C:\\\\a\\\"b");
+        assert_eq!(unescape("caf\\x00e9"), "café");
+        assert_eq!(format_value_line("", &RegData::UInt(0x13)), "@=dword:00000013");
+        assert_eq!(parse_value_line("\"Path\"=str(2):\"%USERPROFILE%\\\\AppData\"").unwrap().1.unwrap(),
+                   RegData::Str(String::from("%USERPROFILE%\\AppData")));
+    }
+
+    #[test]
+    fn registry_paths() {
+        let (dir, registry) = prefix(SYSTEM_REG, "WINE REGISTRY Version 2\n");
+        let game_dir = dir.path().join("drive_c").join("Games").join("BFME2");
+        assert_eq!(registry.to_registry_path(&game_dir), "C:\\Games\\BFME2");
+        assert_eq!(registry.from_registry_path("C:\\Games\\BFME2"), game_dir);
+        assert_eq!(registry.to_registry_path(Path::new("/home/me/BFME2")), "Z:\\home\\me\\BFME2");
+        assert_eq!(registry.from_registry_path("z:\\home\\me\\BFME2"), PathBuf::from("/home/me/BFME2"));
+    }
+
+    #[test]
+    fn appdata_from_shell_folders() {
+        let folders = [
+            ("C:\\\\users\\\\steamuser\\\\AppData\\\\Roaming", "users/steamuser/AppData/Roaming"),
+            ("C:\\\\users\\\\steamuser\\\\Application Data", "users/steamuser/Application Data")
+        ];
+        for (registered, expected) in folders {
+            let user_reg = format!("WINE REGISTRY Version 2\n;; All keys relative to \\\\User\\\\S-1-5-21-0-0-0-1000\n\n#arch=win64\n\n\
+                                    [Software\\\\Microsoft\\\\Windows\\\\CurrentVersion\\\\Explorer\\\\Shell Folders] 1700000000\n\
+                                    \"AppData\"=\"{}\"\n", registered);
+            let (dir, registry) = prefix(SYSTEM_REG, &user_reg);
+            assert_eq!(registry.appdata_path(), dir.path().join("drive_c").join(expected));
+        }
+    }
+
+    #[test]
+    fn appdata_guessed_without_shell_folders() {
+        let (dir, registry) = prefix(SYSTEM_REG, "WINE REGISTRY Version 2\n");
+        let users = dir.path().join("drive_c").join("users");
+        fs::create_dir_all(users.join("Public").join("AppData").join("Roaming")).unwrap();
+        fs::create_dir_all(users.join("olduser").join("Application Data")).unwrap();
+        assert_eq!(registry.appdata_path(), users.join("olduser").join("Application Data"));
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::{self, create_dir_all, remove_dir_all};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use iced_futures::futures;
+
+use crate::common::InstallationProgress;
+
+/// Moves an installation directory. A rename is tried first (same drive); otherwise the files are
+/// copied one by one. A copied installation's old directory is kept until the validation at the new path
+/// confirmed the checksum (see `Installer::take_relocation_source`).
+pub struct Relocation<I> {
+    pub id: I,
+    pub from: String,
+    pub to: String
+}
+
+pub enum RelocationState {
+    Start(PathBuf, PathBuf),
+    Copying(PathBuf, PathBuf, VecDeque<PathBuf>, usize),
+    Finished
+}
+
+/// Checks that the installation can be moved to the target (which must not exist yet or be empty)
+pub fn check_target(from: &str, to: &str) -> Result<(), String> {
+    let from_path = Path::new(from).canonicalize()
+        .map_err(|e| format!("Could not find {}: {}", from, e))?;
+    let to_path = Path::new(to);
+    if to.trim().is_empty() {
+        return Err(String::from("No target directory"))
+    }
+    if to_path.exists() && fs::read_dir(to_path).map(|mut entries| entries.next().is_some()).unwrap_or(true) {
+        return Err(format!("{} already exists and isn't empty", to))
+    }
+    let to_parent = to_path.parent().and_then(|p| p.canonicalize().ok());
+    if to_parent.map_or(false, |parent| parent.starts_with(&from_path)) {
+        return Err(String::from("Can't move the installation into itself"))
+    }
+    Ok(())
+}
+
+/// The files below the directory, plus the directories without any entries (which have to be created as well)
+fn list_entries(dir: &Path) -> Vec<PathBuf> {
+    match dir.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .flat_map(|e| match e.path().is_dir() {
+                true => match list_entries(&e.path()) {
+                    entries if entries.is_empty() => vec![e.path()],
+                    entries => entries
+                },
+                false => vec![e.path()]
+            })
+            .collect(),
+        Err(_) => vec![]
+    }
+}
+
+impl<H, I, T> iced_native::subscription::Recipe<H, I> for Relocation<T>
+where
+    T: 'static + Hash + Copy + Send,
+    H: Hasher,
+{
+    type Output = (T, InstallationProgress);
+
+    fn hash(&self, state: &mut H) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+        self.id.hash(state);
+    }
+
+    fn stream(self: Box<Self>, _input: iced_futures::BoxStream<I>)
+        -> iced_futures::BoxStream<Self::Output>
+    {
+        let id = self.id;
+
+        Box::pin(futures::stream::unfold(
+            RelocationState::Start(PathBuf::from(self.from), PathBuf::from(self.to)),
+            move |state| async move {
+                match state {
+                    RelocationState::Start(from, to) => {
+                        // An empty target directory would make the rename fail on Windows
+                        let _ = fs::remove_dir(&to);
+                        if let Some(parent) = to.parent() {
+                            if let Err(e) = create_dir_all(parent) {
+                                return Some(((id, InstallationProgress::Errored(e.to_string())), RelocationState::Finished))
+                            }
+                        }
+                        match fs::rename(&from, &to) {
+                            Ok(()) => Some(((id, InstallationProgress::Finished), RelocationState::Finished)),
+                            // e.g. another drive, the files are copied instead
+                            Err(_) => {
+                                let files = VecDeque::from(list_entries(&from));
+                                let total = files.len();
+                                Some(((id, InstallationProgress::Relocating(0.0, format!("{} files", total))),
+                                      RelocationState::Copying(from, to, files, total)))
+                            }
+                        }
+                    },
+                    RelocationState::Copying(from, to, mut files, total) => {
+                        match files.pop_front() {
+                            Some(file) => {
+                                let target = to.join(file.strip_prefix(&from).unwrap());
+                                let result = match file.is_dir() {
+                                    true => create_dir_all(&target),
+                                    false => create_dir_all(target.parent().unwrap())
+                                        .and_then(|_| fs::copy(&file, &target).map(|_| ()))
+                                };
+                                match result {
+                                    Ok(_) => {
+                                        let copied = total - files.len();
+                                        Some(((id, InstallationProgress::Relocating(copied as f32 * 100.0 / total as f32,
+                                                                                      format!("file {} of {}", copied, total))),
+                                              RelocationState::Copying(from, to, files, total)))
+                                    },
+                                    Err(e) => {
+                                        // Leave the original installation as it was
+                                        let _ = remove_dir_all(&to);
+                                        Some(((id, InstallationProgress::Errored(format!("Could not copy {:?}: {}", file, e))),
+                                              RelocationState::Finished))
+                                    }
+                                }
+                            },
+                            None => Some(((id, InstallationProgress::Finished), RelocationState::Finished))
+                        }
+                    },
+                    RelocationState::Finished => {
+                        let _: () = iced::futures::future::pending().await;
+                        None
+                    }
+                }
+            }
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn empty_directories_are_listed() {
+        let dir = TempDir::new().unwrap();
+        create_dir_all(dir.path().join("data").join("movies")).unwrap();
+        create_dir_all(dir.path().join("lang")).unwrap();
+        fs::write(dir.path().join("lang").join("english.big"), "").unwrap();
+        fs::write(dir.path().join("game.dat"), "").unwrap();
+
+        let mut entries = list_entries(dir.path());
+        entries.sort();
+        assert_eq!(entries, vec![
+            dir.path().join("data").join("movies"),
+            dir.path().join("game.dat"),
+            dir.path().join("lang").join("english.big")
+        ]);
+    }
+
+    #[test]
+    fn targets() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("BFME2");
+        create_dir_all(from.join("data")).unwrap();
+        let from = from.to_str().unwrap();
+        assert!(check_target(from, dir.path().join("Moved").to_str().unwrap()).is_ok());
+        assert!(check_target(from, "  ").is_err());
+        assert!(check_target(from, dir.path().to_str().unwrap()).is_err());
+        assert!(check_target(from, Path::new(from).join("data").join("BFME2").to_str().unwrap()).is_err());
+        create_dir_all(dir.path().join("Empty")).unwrap();
+        assert!(check_target(from, dir.path().join("Empty").to_str().unwrap()).is_ok());
+    }
+}
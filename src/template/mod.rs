@@ -0,0 +1,49 @@
+use handlebars::Handlebars;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+/// Handlebars setup for registry values and ini files. Unlike `Handlebars::new()` it doesn't HTML-escape
+/// values (an install path containing `&` must not end up as `&amp;`) and fails on unknown variables
+/// instead of rendering them as empty strings.
+static HANDLEBARS: Lazy<Handlebars<'static>> = Lazy::new(|| {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.set_strict_mode(true);
+    handlebars
+});
+
+/// A backslash in front of `{{` (e.g. `{{ install_path }}\{{ executable }}`) is kept as path separator rather
+/// than escaping the expression as Handlebars would.
+pub fn render<T: Serialize>(template: &str, data: &T) -> Result<String, String> {
+    HANDLEBARS.render_template(&template.replace("\\{{", "\\\\{{"), data)
+        .map_err(|e| format!("Could not render template '{}': {}", template, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+
+    fn data(install_path: &str) -> BTreeMap<&'static str, String> {
+        let mut data = BTreeMap::new();
+        data.insert("install_path", String::from(install_path));
+        data
+    }
+
+    #[test]
+    fn values_are_not_escaped() {
+        let path = "C:\\Games\\Tom & Jerry's \"<BFME2>\"";
+        assert_eq!(render("{{ install_path }}\\lotrbfme2.exe", &data(path)).unwrap(),
+                   format!("{}\\lotrbfme2.exe", path));
+    }
+
+    #[test]
+    fn backslash_before_expression() {
+        assert_eq!(render("{{ install_path }}\\{{ install_path }}", &data("C:\\Games")).unwrap(), "C:\\Games\\C:\\Games");
+    }
+
+    #[test]
+    fn unknown_variables_fail() {
+        assert!(render("{{ install_pth }}", &data("C:\\Games")).is_err());
+    }
+}
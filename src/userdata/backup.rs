@@ -0,0 +1,167 @@
+use std::fs::{self, File, OpenOptions, create_dir_all};
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDateTime;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use tar::{Archive, Builder};
+use crate::common::{Game, Installation, app_data_dir};
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// A userdata archive in %APPDATA%\BFME2 Installer\backups\userdata
+#[derive(Debug, Clone)]
+pub struct UserdataBackup {
+    pub path: PathBuf,
+    /// Name of the userdata directory the backup was made of (e.g. bfme2_<checksum>)
+    pub source: String,
+    pub created: NaiveDateTime
+}
+
+pub fn backup_dir() -> PathBuf {
+    app_data_dir().join("backups").join("userdata")
+}
+
+fn userdata_path(installation: &Installation) -> Result<PathBuf, String> {
+    installation.get_userdata_path()
+        .map(PathBuf::from)
+        .ok_or(String::from("Userdata path is not available (checksum missing)"))
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).map(|mut entries| entries.next().is_none()).unwrap_or(true)
+}
+
+/// Creates a new archive file. Backups made within the same second get a counter after the timestamp
+/// (<userdata directory>_<timestamp>.2.tar.gz, ...) instead of replacing each other.
+fn create_archive_file(installation: &Installation) -> Result<(PathBuf, File), String> {
+    create_dir_all(backup_dir()).map_err(|e| e.to_string())?;
+    let name = format!("{}_{}", installation.userdata_leaf_name(), chrono::Local::now().format(TIMESTAMP_FORMAT));
+    for n in 1.. {
+        let target = backup_dir().join(match n {
+            1 => format!("{}.tar.gz", name),
+            n => format!("{}.{}.tar.gz", name, n)
+        });
+        match OpenOptions::new().write(true).create_new(true).open(&target) {
+            Ok(file) => return Ok((target, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Could not create {:?}: {}", target, e))
+        }
+    }
+    unreachable!()
+}
+
+/// Archives the installation's userdata directory as <userdata directory>_<timestamp>.tar.gz
+pub fn create(installation: &Installation) -> Result<PathBuf, String> {
+    let userdata_path = userdata_path(installation)?;
+    if is_empty_dir(&userdata_path) {
+        return Err(format!("{:?} doesn't exist or is empty", userdata_path))
+    }
+    let (target, file) = create_archive_file(installation)?;
+    println!("Backing up {:?} to {:?}...", userdata_path, target);
+
+    let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.append_dir_all(".", &userdata_path)
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| {
+            let _ = fs::remove_file(&target);
+            format!("Could not write {:?}: {}", target, e)
+        })?;
+    Ok(target)
+}
+
+/// Backs up the userdata directory if there is anything in it, e.g. before it gets overwritten
+pub fn create_if_present(installation: &Installation) -> Result<Option<PathBuf>, String> {
+    match is_empty_dir(&userdata_path(installation)?) {
+        true => Ok(None),
+        false => create(installation).map(Some)
+    }
+}
+
+/// The userdata backups of the game's installations (of any checksum or profile), newest first
+pub fn list(game: &Game) -> Vec<UserdataBackup> {
+    let prefix = format!("{}_", game.definition().userdata_prefix);
+    let mut backups = fs::read_dir(backup_dir())
+        .map(|entries| entries.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let file_name = e.file_name().to_string_lossy().to_string();
+                let (source, timestamp) = file_name.strip_suffix(".tar.gz")?.rsplit_once('_')?;
+                if !source.starts_with(&prefix) {
+                    return None
+                }
+                // Without the counter of backups made within the same second
+                let timestamp = timestamp.split('.').next()?;
+                let created = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+                Some(UserdataBackup { path: e.path(), source: source.to_string(), created })
+            })
+            .collect::<Vec<UserdataBackup>>())
+        .unwrap_or_default();
+    backups.sort_by(|a, b| b.created.cmp(&a.created).then(b.path.cmp(&a.path)));
+    backups
+}
+
+/// Extracts the backup into the installation's userdata directory, which doesn't have to be the one the
+/// backup was made of. Files missing from the backup are kept. The current userdata is backed up first.
+pub fn restore(backup: &UserdataBackup, installation: &Installation) -> Result<(), String> {
+    let userdata_path = userdata_path(installation)?;
+    create_if_present(installation)?;
+
+    println!("Restoring {:?} to {:?}...", backup.path, userdata_path);
+    create_dir_all(&userdata_path).map_err(|e| e.to_string())?;
+    let file = File::open(&backup.path).map_err(|e| format!("Could not open {:?}: {}", backup.path, e))?;
+    Archive::new(GzDecoder::new(file))
+        .unpack(&userdata_path)
+        .map_err(|e| format!("Could not extract {:?}: {}", backup.path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::reg::backend::test_registry;
+    use super::*;
+
+    fn installation(game: &str, checksum: &str) -> Installation {
+        let mut installation = Installation::defaults(Game::from_id(game).unwrap());
+        installation.checksum = String::from(checksum);
+        let userdata_path = PathBuf::from(installation.get_userdata_path().unwrap());
+        create_dir_all(userdata_path.join("Profiles")).unwrap();
+        fs::write(userdata_path.join("Options.ini"), "Resolution = 800 600\r\n").unwrap();
+        installation
+    }
+
+    #[test]
+    fn backups_within_a_second_are_kept() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let installation = installation("BFME2", "abababababababababababababababab");
+        let first = create(&installation).unwrap();
+        let second = create(&installation).unwrap();
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+
+        let backups = list(&installation.game).into_iter()
+            .filter(|backup| backup.source == installation.userdata_leaf_name())
+            .collect::<Vec<UserdataBackup>>();
+        assert_eq!(backups.len(), 2);
+        assert!(list(&Game::from_id("ROTWK").unwrap()).iter().all(|backup| backup.source.starts_with("rotwk_")));
+    }
+
+    #[test]
+    fn restore_into_another_installation() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let source = installation("ROTWK", "cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd");
+        let backup_path = create(&source).unwrap();
+        let backup = list(&source.game).into_iter().find(|backup| backup.path == backup_path).unwrap();
+
+        let mut target = Installation::defaults(source.game);
+        target.checksum = String::from("efefefefefefefefefefefefefefefef");
+        restore(&backup, &target).unwrap();
+        let restored = PathBuf::from(target.get_userdata_path().unwrap());
+        assert_eq!(fs::read_to_string(restored.join("Options.ini")).unwrap(), "Resolution = 800 600\r\n");
+        assert!(restored.join("Profiles").is_dir());
+    }
+}
+
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::checksums::calculate_hash;
+use crate::common::Installation;
+use crate::reg::backend::registry;
+
+pub mod backup;
+
+/// Records which installation checksum the userdata files were created under
+const ORIGINS_FILE: &str = "bfme2_installer_origins.json";
+const QUARANTINE_DIR: &str = "Quarantine";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Origin {
+    hash: String,
+    checksum: String
+}
+
+#[derive(Debug, Clone)]
+pub struct ScannedFile {
+    pub path: String,
+    pub origin: String,
+    pub tainted: bool
+}
+
+/// Create-a-Hero and profile files can cause out of sync errors if they have been
+/// created with a different version of the game
+fn is_scanned_file(rel_path: &Path) -> bool {
+    let is_profile = rel_path.components().next()
+        .map(|c| c.as_os_str().to_string_lossy().to_lowercase() == "profiles")
+        .unwrap_or(false);
+    let is_hero = rel_path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase() == "cah")
+        .unwrap_or(false);
+    is_profile || is_hero
+}
+
+fn list_files(root: &Path, dir: &Path) -> Vec<PathBuf> {
+    match dir.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| !(dir == root && e.file_name() == QUARANTINE_DIR))
+            .flat_map(|e| match e.path().is_dir() {
+                true => list_files(root, &e.path()),
+                false => vec![e.path()]
+            })
+            .collect(),
+        Err(_) => vec![]
+    }
+}
+
+fn read_origins(userdata_path: &Path) -> BTreeMap<String, Origin> {
+    fs::read_to_string(userdata_path.join(ORIGINS_FILE)).ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write_origins(userdata_path: &Path, origins: &BTreeMap<String, Origin>) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(origins).map_err(|e| e.to_string())?;
+    File::create(userdata_path.join(ORIGINS_FILE))
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .map_err(|e| e.to_string())
+}
+
+/// Origins recorded in the userdata directories of the game's other versions (<userdata_prefix>_<checksum>)
+fn read_sibling_origins(installation: &Installation, userdata_path: &Path) -> Vec<Origin> {
+    let prefix = installation.game.userdata_leaf_name("");
+    match userdata_path.parent().and_then(|p| p.read_dir().ok()) {
+        Some(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path() != userdata_path && e.file_name().to_string_lossy().starts_with(&prefix))
+            .flat_map(|e| read_origins(&e.path()).into_values())
+            .collect(),
+        None => vec![]
+    }
+}
+
+/// Scans the installation's userdata directory for Create-a-Hero and profile files.
+///
+/// Files which haven't been seen before are attributed to the installation's checksum, unless
+/// an identical file has been recorded in the userdata directory of another version of the game
+/// (i.e. it has been copied from there). Files from another version are flagged as tainted.
+pub fn scan(installation: &Installation) -> Result<Vec<ScannedFile>, String> {
+    let userdata_path = PathBuf::from(installation.get_userdata_path()
+        .ok_or("Userdata path is not available (checksum missing)")?);
+    if installation.checksum.is_empty() {
+        return Err(String::from("Installation has no checksum, please validate it first"));
+    }
+
+    let mut origins = read_origins(&userdata_path);
+    let siblings = read_sibling_origins(installation, &userdata_path);
+
+    let mut results = list_files(&userdata_path, &userdata_path).into_iter()
+        .filter_map(|path| path.strip_prefix(&userdata_path).ok().map(|p| p.to_owned()))
+        .filter(|rel_path| is_scanned_file(rel_path))
+        .map(|rel_path| {
+            let rel_path_str = rel_path.to_string_lossy().into_owned();
+            let hash = calculate_hash(userdata_path.join(&rel_path))?;
+            let origin = match origins.get(&rel_path_str) {
+                Some(origin) if origin.hash == hash => origin.checksum.clone(),
+                _ => siblings.iter()
+                    .find(|o| o.hash == hash)
+                    .map(|o| o.checksum.clone())
+                    .unwrap_or(installation.checksum.clone())
+            };
+            origins.insert(rel_path_str.clone(), Origin { hash, checksum: origin.clone() });
+            Ok(ScannedFile {
+                path: rel_path_str,
+                tainted: origin != installation.checksum,
+                origin
+            })
+        })
+        .collect::<Result<Vec<ScannedFile>, String>>()?;
+    results.sort_by(|f1, f2| f1.path.cmp(&f2.path));
+
+    origins.retain(|path, _| userdata_path.join(path).exists());
+    write_origins(&userdata_path, &origins)?;
+    Ok(results)
+}
+
+/// Moves the given files to <userdata>/Quarantine/<timestamp>/, keeping their relative paths. Their origins are
+/// kept in the quarantine directory, so they are still attributed to the other version if they are restored.
+pub fn quarantine(installation: &Installation, files: &Vec<ScannedFile>) -> Result<PathBuf, String> {
+    let userdata_path = PathBuf::from(installation.get_userdata_path()
+        .ok_or("Userdata path is not available (checksum missing)")?);
+    let quarantine_path = userdata_path.join(QUARANTINE_DIR)
+        .join(chrono::Local::now().format("%Y%m%d-%H%M%S").to_string());
+
+    create_dir_all(&quarantine_path).map_err(|e| e.to_string())?;
+    let mut origins = read_origins(&userdata_path);
+    let mut quarantined = BTreeMap::new();
+    for file in files {
+        let target = quarantine_path.join(&file.path);
+        println!("Moving {} to {:?}...", file.path, target);
+        create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::rename(userdata_path.join(&file.path), &target).map_err(|e| e.to_string())?;
+        if let Some(origin) = origins.remove(&file.path) {
+            quarantined.insert(file.path.clone(), origin);
+        }
+    }
+    write_origins(&userdata_path, &origins)?;
+    write_origins(&quarantine_path, &quarantined)?;
+    Ok(quarantine_path)
+}
+
+/// Moves quarantined files back to the userdata directory (see `quarantine`) and removes the quarantine directory.
+/// Files which have been replaced in the meantime are left in the quarantine. Returns the number of restored files.
+pub fn restore_quarantined(installation: &Installation, quarantine_path: &Path) -> Result<usize, String> {
+    let userdata_path = PathBuf::from(installation.get_userdata_path()
+        .ok_or("Userdata path is not available (checksum missing)")?);
+    if !quarantine_path.starts_with(userdata_path.join(QUARANTINE_DIR)) {
+        return Err(format!("{:?} is not a quarantine of this installation", quarantine_path))
+    }
+
+    let mut origins = read_origins(&userdata_path);
+    let mut quarantined = read_origins(quarantine_path);
+    let mut restored = 0;
+    for source in list_files(quarantine_path, quarantine_path) {
+        let rel_path = source.strip_prefix(quarantine_path).unwrap().to_owned();
+        if rel_path == Path::new(ORIGINS_FILE) {
+            continue;
+        }
+        let target = userdata_path.join(&rel_path);
+        if target.exists() {
+            println!("Keeping {:?} in the quarantine, {:?} exists", rel_path, target);
+            continue;
+        }
+        create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::rename(&source, &target).map_err(|e| format!("Could not restore {:?}: {}", rel_path, e))?;
+        let rel_path = rel_path.to_string_lossy().into_owned();
+        if let Some(origin) = quarantined.remove(&rel_path) {
+            origins.insert(rel_path, origin);
+        }
+        restored += 1;
+    }
+    write_origins(&userdata_path, &origins)?;
+    match list_files(quarantine_path, quarantine_path).into_iter().all(|path| path.ends_with(ORIGINS_FILE)) {
+        true => fs::remove_dir_all(quarantine_path).map_err(|e| e.to_string())?,
+        false => write_origins(quarantine_path, &quarantined)?
+    }
+    Ok(restored)
+}
+
+/// Copies the userdata of a retail installation (e.g. "My Battle for Middle-earth(tm) II Files") to the
+/// installation's own userdata directory. Files which already exist there are kept. The retail directory
+/// is left as it is, so the retail registry entries (see the registry backup) stay usable.
+/// Returns the number of copied files.
+pub fn migrate_retail(installation: &Installation) -> Result<usize, String> {
+    let retail_dir = match &installation.game.definition().retail_userdata_dir {
+        Some(dir) => registry().appdata_path().join(dir),
+        None => return Ok(0)
+    };
+    if !retail_dir.is_dir() {
+        println!("No retail userdata at {:?}", retail_dir);
+        return Ok(0)
+    }
+    let userdata_path = PathBuf::from(installation.get_userdata_path()
+        .ok_or("Userdata path is not available (checksum missing)")?);
+
+    let mut copied = 0;
+    for source in list_files(&retail_dir, &retail_dir) {
+        let target = userdata_path.join(source.strip_prefix(&retail_dir).unwrap());
+        if target.exists() {
+            continue;
+        }
+        println!("Copying {:?} to {:?}...", source, target);
+        create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::copy(&source, &target).map_err(|e| format!("Could not copy {:?}: {}", source, e))?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Moves the userdata directories of the named profiles to the names for the installation's new checksum
+/// (`previous` is the installation before the checksum changed). Directories which another installation uses
+/// as well are left where they are; the names of their profiles are returned.
+pub fn migrate_profiles(previous: &Installation, installation: &Installation, in_use: &[String]) -> Result<Vec<String>, String> {
+    let mut kept = vec![];
+    for profile in &installation.profiles {
+        let (from, to) = match (previous.with_profile(Some(profile.clone())).get_userdata_path(),
+                                installation.with_profile(Some(profile.clone())).get_userdata_path()) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue
+        };
+        if !Path::new(&from).is_dir() || Path::new(&to).exists() {
+            continue;
+        }
+        if in_use.iter().any(|path| path.to_lowercase() == from.to_lowercase()) {
+            kept.push(profile.clone());
+            continue;
+        }
+        println!("Moving {} to {}...", from, to);
+        fs::rename(&from, &to).map_err(|e| format!("Could not move {}: {}", from, e))?;
+    }
+    Ok(kept)
+}
+
+/// Creates the userdata directory of the installation's current profile if it doesn't exist yet. New profiles
+/// start with the files of the default profile except profiles and heroes (see `is_scanned_file`) and get
+/// the installation's Options.ini settings.
+pub fn ensure_profile(installation: &Installation) -> Result<(), String> {
+    let userdata_path = PathBuf::from(installation.get_userdata_path()
+        .ok_or("Userdata path is not available (checksum missing)")?);
+    if userdata_path.is_dir() {
+        return Ok(())
+    }
+    println!("Creating userdata directory {:?}...", userdata_path);
+    create_dir_all(&userdata_path).map_err(|e| e.to_string())?;
+
+    if let Some(default_path) = installation.with_profile(None).get_userdata_path().map(PathBuf::from) {
+        for source in list_files(&default_path, &default_path) {
+            let rel_path = source.strip_prefix(&default_path).unwrap();
+            if is_scanned_file(rel_path) || rel_path == Path::new(ORIGINS_FILE) {
+                continue;
+            }
+            let target = userdata_path.join(rel_path);
+            create_dir_all(target.parent().unwrap()).map_err(|e| e.to_string())?;
+            fs::copy(&source, &target).map_err(|e| format!("Could not copy {:?}: {}", source, e))?;
+        }
+    }
+    installation.write_options()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use crate::common::Game;
+    use crate::reg::backend::test_registry;
+    use super::*;
+
+    #[test]
+    fn migrate_retail_keeps_options() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let game = Game::from_id("BFME2").unwrap();
+        let retail_dir = dir.path().join(game.definition().retail_userdata_dir.as_ref().unwrap());
+        let retail_options = "Resolution = 2560 1440\r\nMusicVolume = 40.000000\r\n";
+        create_dir_all(retail_dir.join("Profiles")).unwrap();
+        fs::write(retail_dir.join("Options.ini"), retail_options).unwrap();
+        fs::write(retail_dir.join("Profiles").join("Player"), "profile").unwrap();
+
+        let mut installation = Installation::defaults(game);
+        installation.checksum = String::from("0123456789abcdef0123456789abcdef");
+        assert_eq!(migrate_retail(&installation).unwrap(), 2);
+        let options = installation.read_options().unwrap();
+        assert_eq!(options.to_string(), retail_options);
+        assert_eq!(options.resolution(), Some((2560, 1440)));
+        // Files which exist already aren't replaced
+        fs::write(retail_dir.join("Profiles").join("Player"), "changed").unwrap();
+        assert_eq!(migrate_retail(&installation).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_profiles_to_new_checksum() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let mut previous = Installation::defaults(Game::from_id("ROTWK").unwrap());
+        previous.checksum = String::from("11111111111111111111111111111111");
+        previous.profiles = vec![String::from("Anna"), String::from("Ben"), String::from("New")];
+        let anna = previous.with_profile(Some(String::from("Anna"))).get_userdata_path().unwrap();
+        let ben = previous.with_profile(Some(String::from("Ben"))).get_userdata_path().unwrap();
+        create_dir_all(&anna).unwrap();
+        fs::write(Path::new(&anna).join("Options.ini"), "Resolution = 800 600\r\n").unwrap();
+        create_dir_all(&ben).unwrap();
+
+        let mut installation = previous.clone();
+        installation.checksum = String::from("22222222222222222222222222222222");
+        assert_eq!(migrate_profiles(&previous, &installation, &[ben.clone()]).unwrap(), vec![String::from("Ben")]);
+        let moved = installation.with_profile(Some(String::from("Anna"))).get_userdata_path().unwrap();
+        assert_eq!(fs::read_to_string(Path::new(&moved).join("Options.ini")).unwrap(), "Resolution = 800 600\r\n");
+        assert!(!Path::new(&anna).exists());
+        assert!(Path::new(&ben).exists());
+    }
+
+    fn fixture(game: &str, checksum: &str) -> (Installation, PathBuf) {
+        let mut installation = Installation::defaults(Game::from_id(game).unwrap());
+        installation.checksum = String::from(checksum);
+        let userdata_path = PathBuf::from(installation.get_userdata_path().unwrap());
+        create_dir_all(userdata_path.join("Profiles").join("Anna")).unwrap();
+        (installation, userdata_path)
+    }
+
+    fn flagged(files: &[ScannedFile]) -> Vec<&str> {
+        files.iter().filter(|f| f.tainted).map(|f| f.path.as_str()).collect()
+    }
+
+    #[test]
+    fn scan_flags_files_of_other_versions() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let (installation, userdata_path) = fixture("BFME2", "11111111111111111111111111111111");
+        let (other, other_path) = fixture("BFME2", "22222222222222222222222222222222");
+        let profile = Path::new("Profiles").join("Anna").join("Player.ini");
+        fs::write(userdata_path.join("Aragorn.cah"), "hero 1").unwrap();
+        fs::write(userdata_path.join(&profile), "profile 1").unwrap();
+        fs::write(userdata_path.join("Options.ini"), "Resolution = 800 600\r\n").unwrap();
+        fs::write(other_path.join("Gandalf.cah"), "hero 2").unwrap();
+        fs::write(other_path.join(&profile), "profile 2").unwrap();
+
+        // Files created with each version aren't flagged, other files aren't scanned
+        let files = scan(&installation).unwrap();
+        assert_eq!(files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+                   vec!["Aragorn.cah", profile.to_str().unwrap()]);
+        assert!(flagged(&files).is_empty());
+        assert!(flagged(&scan(&other).unwrap()).is_empty());
+
+        // Files copied from the other version are flagged, the others stay unflagged
+        fs::copy(other_path.join("Gandalf.cah"), userdata_path.join("Gandalf.cah")).unwrap();
+        fs::copy(other_path.join(&profile), userdata_path.join(&profile)).unwrap();
+        let files = scan(&installation).unwrap();
+        assert_eq!(flagged(&files), vec!["Gandalf.cah", profile.to_str().unwrap()]);
+        assert!(files.iter().filter(|f| f.tainted).all(|f| f.origin == other.checksum));
+        // The origin is kept on later scans
+        assert_eq!(flagged(&scan(&installation).unwrap()).len(), 2);
+
+        // A flagged file which has been saved again with this version is no longer flagged
+        fs::write(userdata_path.join("Gandalf.cah"), "hero 3").unwrap();
+        assert_eq!(flagged(&scan(&installation).unwrap()), vec![profile.to_str().unwrap()]);
+    }
+
+    #[test]
+    fn quarantine_and_restore() {
+        let dir = TempDir::new().unwrap();
+        test_registry().set_appdata_path(dir.path());
+        let (installation, userdata_path) = fixture("ROTWK", "11111111111111111111111111111111");
+        let (other, other_path) = fixture("ROTWK", "22222222222222222222222222222222");
+        fs::write(userdata_path.join("Aragorn.cah"), "hero 1").unwrap();
+        fs::write(other_path.join("Gandalf.cah"), "hero 2").unwrap();
+        scan(&other).unwrap();
+        fs::copy(other_path.join("Gandalf.cah"), userdata_path.join("Gandalf.cah")).unwrap();
+
+        let tainted = scan(&installation).unwrap().into_iter().filter(|f| f.tainted).collect::<Vec<_>>();
+        assert_eq!(tainted.len(), 1);
+        let quarantine_path = quarantine(&installation, &tainted).unwrap();
+        assert!(quarantine_path.join("Gandalf.cah").exists());
+        assert!(!userdata_path.join("Gandalf.cah").exists());
+        let files = scan(&installation).unwrap();
+        assert_eq!(files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["Aragorn.cah"]);
+
+        // Restored files keep their origin
+        assert_eq!(restore_quarantined(&installation, &quarantine_path).unwrap(), 1);
+        assert_eq!(fs::read_to_string(userdata_path.join("Gandalf.cah")).unwrap(), "hero 2");
+        assert!(!quarantine_path.exists());
+        assert_eq!(flagged(&scan(&installation).unwrap()), vec!["Gandalf.cah"]);
+
+        // Files which have been replaced in the meantime are kept in the quarantine
+        let quarantine_path = quarantine(&installation, &scan(&installation).unwrap()).unwrap();
+        fs::write(userdata_path.join("Aragorn.cah"), "hero 3").unwrap();
+        assert_eq!(restore_quarantined(&installation, &quarantine_path).unwrap(), 1);
+        assert_eq!(fs::read_to_string(userdata_path.join("Aragorn.cah")).unwrap(), "hero 3");
+        assert_eq!(fs::read_to_string(quarantine_path.join("Aragorn.cah")).unwrap(), "hero 1");
+        assert!(restore_quarantined(&other, &quarantine_path).is_err());
+    }
+}